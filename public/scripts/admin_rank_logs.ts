@@ -25,8 +25,9 @@ createPaginatedPage(async (page) => {
   return response.text();
 }, adminRankLogs);
 
-const extraStyle = document.createElement("style");
-document.head.appendChild(extraStyle);
+// Built through CSSOM, as the Content-Security-Policy doesn't allow inline styles
+const extraStyle = new CSSStyleSheet();
+document.adoptedStyleSheets = [...document.adoptedStyleSheets, extraStyle];
 
 const operationsToggled = new Set(OPERATIONS);
 
@@ -46,15 +47,17 @@ for (const [index, operation] of Object.entries(OPERATIONS)) {
       operationsToggled.delete(operation);
     }
 
-    extraStyle.innerHTML = "";
+    let rules = "";
 
     for (const toggledOperation of OPERATIONS) {
       if (operationsToggled.has(toggledOperation)) {
         continue;
       }
 
-      extraStyle.innerHTML += `.rank-log[data-operation="${toggledOperation}"] { display: none; }`;
+      rules += `.rank-log[data-operation="${toggledOperation}"] { display: none; }`;
     }
+
+    extraStyle.replaceSync(rules);
   });
 
   const label = document.createElement("label");
@@ -0,0 +1,9 @@
+// The Content-Security-Policy doesn't allow javascript: links, so going back is done here
+const backLink = document.getElementById("back_link")!;
+
+backLink.addEventListener("click", (event) => {
+  if (history.length > 1) {
+    event.preventDefault();
+    history.back();
+  }
+});
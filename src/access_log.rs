@@ -0,0 +1,195 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{FromRequest, RequestParts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use color_eyre::eyre::Context;
+use http::{Request, StatusCode};
+use serde::{Deserialize, Serialize};
+use sqlx::{MySql, QueryBuilder, Row};
+
+use crate::{auth::AuthenticatedUserOptional, state::User, State};
+
+pub const ENTRIES_PER_PAGE: u32 = 50;
+
+/// A privileged view, one for each place an admin can see what players can't.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessKind {
+    /// Someone else's tickets. The target is their ckey.
+    PlayerTickets,
+    /// The target is the server name.
+    ServerTickets,
+    /// The target is the round id.
+    RoundTickets,
+    /// A single ticket the viewer wasn't part of. The target is `<round>/<ticket>`.
+    Ticket,
+    /// The target is the poll id, or `*` for the results of every poll in `/polls.json`.
+    AdminOnlyPoll,
+    /// Who wrote which answers to a text poll. The target is the poll id.
+    TextPollCkeys,
+    /// The target is the path in the panel.
+    Evasion,
+}
+
+impl AccessKind {
+    pub const ALL: [AccessKind; 7] = [
+        AccessKind::PlayerTickets,
+        AccessKind::ServerTickets,
+        AccessKind::RoundTickets,
+        AccessKind::Ticket,
+        AccessKind::AdminOnlyPoll,
+        AccessKind::TextPollCkeys,
+        AccessKind::Evasion,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AccessKind::PlayerTickets => "player_tickets",
+            AccessKind::ServerTickets => "server_tickets",
+            AccessKind::RoundTickets => "round_tickets",
+            AccessKind::Ticket => "ticket",
+            AccessKind::AdminOnlyPoll => "admin_only_poll",
+            AccessKind::TextPollCkeys => "text_poll_ckeys",
+            AccessKind::Evasion => "evasion",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccessLogEntry {
+    pub viewer: String,
+    pub kind: Option<AccessKind>,
+    pub target: String,
+    pub viewed_at: chrono::NaiveDateTime,
+}
+
+/// Narrows down the log. Every given field has to match.
+#[derive(Debug, Default)]
+pub struct AccessLogFilter<'a> {
+    pub viewer: Option<&'a str>,
+    pub kind: Option<AccessKind>,
+    pub target: Option<&'a str>,
+}
+
+/// Records that the user looked at something privileged. Failing to do so is logged rather than failing the view.
+#[tracing::instrument(skip(state, viewer), fields(viewer = %viewer.ckey))]
+pub async fn record(state: &State, viewer: &User, kind: AccessKind, target: &str) {
+    if let Err(error) = sqlx::query(
+        "INSERT INTO mothbus_access_log (viewer, kind, target, viewed_at) VALUES (?, ?, ?, ?)",
+    )
+    .bind(&viewer.ckey)
+    .bind(kind.name())
+    .bind(target)
+    .bind(chrono::Utc::now().naive_utc())
+    .execute(&state.mysql_pool)
+    .await
+    {
+        tracing::error!("failed to record privileged access: {error:#}");
+    }
+}
+
+/// The evasion panel checks who can see it on its own, so successful views of it are recorded from outside.
+pub async fn record_evasion_views<B: Send>(request: Request<B>, next: Next<B>) -> Response {
+    let path = request.uri().path().to_owned();
+
+    let mut request_parts = RequestParts::new(request);
+    let user = match AuthenticatedUserOptional::from_request(&mut request_parts).await {
+        Ok(AuthenticatedUserOptional(user)) => user,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    let request = match request_parts.try_into_request() {
+        Ok(request) => request,
+        Err(error) => {
+            tracing::error!("couldn't rebuild request after getting user: {error:#?}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let state = Arc::clone(
+        request
+            .extensions()
+            .get::<Arc<State>>()
+            .expect("can't get state"),
+    );
+
+    let response = next.run(request).await;
+
+    if let Some(user) = user {
+        if response.status().is_success() {
+            record(&state, &user, AccessKind::Evasion, &path).await;
+        }
+    }
+
+    response
+}
+
+/// Newest first, pages starting at 1.
+#[tracing::instrument(skip(mysql_pool))]
+pub async fn entries(
+    mysql_pool: &sqlx::MySqlPool,
+    filter: &AccessLogFilter<'_>,
+    page: u32,
+) -> color_eyre::Result<Vec<AccessLogEntry>> {
+    let mut query: QueryBuilder<MySql> =
+        QueryBuilder::new("SELECT viewer, kind, target, viewed_at FROM mothbus_access_log WHERE 1");
+
+    if let Some(viewer) = filter.viewer {
+        query.push(" AND viewer = ").push_bind(viewer);
+    }
+
+    if let Some(kind) = filter.kind {
+        query.push(" AND kind = ").push_bind(kind.name());
+    }
+
+    if let Some(target) = filter.target {
+        query.push(" AND target = ").push_bind(target);
+    }
+
+    query
+        .push(" ORDER BY id DESC LIMIT ")
+        .push_bind(ENTRIES_PER_PAGE)
+        .push(" OFFSET ")
+        .push_bind(u64::from(page.saturating_sub(1)) * u64::from(ENTRIES_PER_PAGE));
+
+    query
+        .build()
+        .fetch_all(mysql_pool)
+        .await
+        .context("failed to fetch access log")?
+        .iter()
+        .map(|row| {
+            Ok(AccessLogEntry {
+                viewer: row.try_get("viewer")?,
+                kind: AccessKind::from_name(row.try_get("kind")?),
+                target: row.try_get("target")?,
+                viewed_at: row.try_get("viewed_at")?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kind_names_match_serde() {
+        for kind in AccessKind::ALL {
+            assert_eq!(
+                serde_json::to_value(kind).unwrap(),
+                serde_json::json!(kind.name())
+            );
+            assert_eq!(AccessKind::from_name(kind.name()), Some(kind));
+        }
+
+        assert_eq!(AccessKind::from_name("unknown"), None);
+    }
+}
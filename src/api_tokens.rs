@@ -0,0 +1,325 @@
+use color_eyre::eyre::Context;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use sqlx::{mysql::MySqlRow, Row};
+
+use crate::State;
+
+const TOKEN_PREFIX: &str = "mothbus_";
+const REQUEST_RETENTION_DAYS: i64 = 90;
+
+/// What a token is allowed to do. Tokens never get more than the admin who created them.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Scope {
+    #[serde(rename = "tickets:read")]
+    TicketsRead,
+    #[serde(rename = "polls:read")]
+    PollsRead,
+    /// Who wrote which text poll replies, which `polls:read` alone doesn't show.
+    #[serde(rename = "polls:read_ckeys")]
+    PollsReadCkeys,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 3] = [Scope::TicketsRead, Scope::PollsRead, Scope::PollsReadCkeys];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scope::TicketsRead => "tickets:read",
+            Scope::PollsRead => "polls:read",
+            Scope::PollsReadCkeys => "polls:read_ckeys",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|scope| scope.name() == name)
+    }
+}
+
+/// A token that was presented with a request, and is valid.
+#[derive(Clone, Debug)]
+pub struct ApiToken {
+    pub id: u64,
+    pub label: String,
+    pub scopes: Vec<Scope>,
+    pub created_by: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StoredApiToken {
+    pub id: u64,
+    pub label: String,
+    pub scopes: Vec<Scope>,
+    pub created_by: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+}
+
+impl StoredApiToken {
+    fn from_row(row: &MySqlRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            label: row.try_get("label")?,
+            scopes: parse_scopes(&row.try_get::<String, _>("scopes")?),
+            created_by: row.try_get("created_by")?,
+            created_at: row.try_get("created_at")?,
+            expires_at: row.try_get("expires_at")?,
+            revoked_at: row.try_get("revoked_at")?,
+            last_used_at: row.try_get("last_used_at")?,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiTokenRequest {
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub ip: String,
+    pub requested_at: chrono::NaiveDateTime,
+}
+
+pub struct NewApiToken<'a> {
+    pub label: &'a str,
+    pub scopes: &'a [Scope],
+    pub created_by: &'a str,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Returns the token itself, which is only stored hashed and can't be shown again.
+#[tracing::instrument(skip_all)]
+pub async fn create(
+    mysql_pool: &sqlx::MySqlPool,
+    token: &NewApiToken<'_>,
+) -> color_eyre::Result<String> {
+    let secret: String = rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect();
+
+    let secret = format!("{TOKEN_PREFIX}{secret}");
+
+    sqlx::query(
+        "INSERT INTO mothbus_api_tokens (label, token_hash, scopes, created_by, created_at, expires_at)
+        VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(token.label)
+    .bind(hash_token(&secret))
+    .bind(
+        token
+            .scopes
+            .iter()
+            .map(|scope| scope.name())
+            .collect::<Vec<_>>()
+            .join(","),
+    )
+    .bind(token.created_by)
+    .bind(chrono::Utc::now().naive_utc())
+    .bind(token.expires_at)
+    .execute(mysql_pool)
+    .await
+    .context("failed to insert api token")?;
+
+    Ok(secret)
+}
+
+/// Returns the token if it exists, and hasn't been revoked or expired.
+#[tracing::instrument(skip_all)]
+pub async fn find_valid(
+    mysql_pool: &sqlx::MySqlPool,
+    secret: &str,
+) -> color_eyre::Result<Option<ApiToken>> {
+    if !secret.starts_with(TOKEN_PREFIX) {
+        return Ok(None);
+    }
+
+    let Some(row) = sqlx::query(
+        "SELECT id, label, scopes, created_by
+        FROM mothbus_api_tokens
+        WHERE token_hash = ?
+            AND revoked_at IS NULL
+            AND (expires_at IS NULL OR expires_at > ?)",
+    )
+    .bind(hash_token(secret))
+    .bind(chrono::Utc::now().naive_utc())
+    .fetch_optional(mysql_pool)
+    .await
+    .context("failed to fetch api token")?
+    else {
+        return Ok(None);
+    };
+
+    Ok(Some(ApiToken {
+        id: row.try_get("id")?,
+        label: row.try_get("label")?,
+        scopes: parse_scopes(&row.try_get::<String, _>("scopes")?),
+        created_by: row.try_get("created_by")?,
+    }))
+}
+
+#[tracing::instrument(skip(mysql_pool))]
+pub async fn tokens(mysql_pool: &sqlx::MySqlPool) -> color_eyre::Result<Vec<StoredApiToken>> {
+    let rows = sqlx::query(
+        "SELECT id, label, scopes, created_by, created_at, expires_at, revoked_at, last_used_at
+        FROM mothbus_api_tokens
+        ORDER BY id DESC",
+    )
+    .fetch_all(mysql_pool)
+    .await
+    .context("failed to fetch api tokens")?;
+
+    rows.iter()
+        .map(|row| StoredApiToken::from_row(row).map_err(Into::into))
+        .collect()
+}
+
+/// Returns the token along with its most recent requests.
+#[tracing::instrument(skip(mysql_pool))]
+pub async fn token(
+    mysql_pool: &sqlx::MySqlPool,
+    id: u64,
+) -> color_eyre::Result<Option<(StoredApiToken, Vec<ApiTokenRequest>)>> {
+    let Some(row) = sqlx::query(
+        "SELECT id, label, scopes, created_by, created_at, expires_at, revoked_at, last_used_at
+        FROM mothbus_api_tokens
+        WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_optional(mysql_pool)
+    .await
+    .context("failed to fetch api token")?
+    else {
+        return Ok(None);
+    };
+
+    let requests = sqlx::query(
+        "SELECT method, path, status, ip, requested_at
+        FROM mothbus_api_token_requests
+        WHERE token_id = ?
+        ORDER BY id DESC
+        LIMIT 200",
+    )
+    .bind(id)
+    .fetch_all(mysql_pool)
+    .await
+    .context("failed to fetch api token requests")?
+    .iter()
+    .map(|row| {
+        Ok(ApiTokenRequest {
+            method: row.try_get("method")?,
+            path: row.try_get("path")?,
+            status: row.try_get("status")?,
+            ip: row.try_get("ip")?,
+            requested_at: row.try_get("requested_at")?,
+        })
+    })
+    .collect::<Result<Vec<_>, sqlx::Error>>()?;
+
+    Ok(Some((StoredApiToken::from_row(&row)?, requests)))
+}
+
+/// Returns false if there was no such unrevoked token.
+#[tracing::instrument(skip(mysql_pool))]
+pub async fn revoke(mysql_pool: &sqlx::MySqlPool, id: u64) -> color_eyre::Result<bool> {
+    let result = sqlx::query(
+        "UPDATE mothbus_api_tokens
+        SET revoked_at = ?
+        WHERE id = ? AND revoked_at IS NULL",
+    )
+    .bind(chrono::Utc::now().naive_utc())
+    .bind(id)
+    .execute(mysql_pool)
+    .await
+    .context("failed to revoke api token")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Records a request made with a token. Failing to do so is logged rather than failing the request.
+#[tracing::instrument(skip(state))]
+pub async fn record_request(
+    state: &State,
+    token: &ApiToken,
+    method: &str,
+    path: &str,
+    status: u16,
+    ip: &str,
+) {
+    if let Err(error) = try_record_request(state, token, method, path, status, ip).await {
+        tracing::error!("failed to record api token request: {error:#}");
+    }
+}
+
+async fn try_record_request(
+    state: &State,
+    token: &ApiToken,
+    method: &str,
+    path: &str,
+    status: u16,
+    ip: &str,
+) -> color_eyre::Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+
+    sqlx::query(
+        "INSERT INTO mothbus_api_token_requests (token_id, method, path, status, ip, requested_at)
+        VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(token.id)
+    .bind(method)
+    .bind(path)
+    .bind(status)
+    .bind(ip)
+    .bind(now)
+    .execute(&state.mysql_pool)
+    .await
+    .context("failed to insert api token request")?;
+
+    sqlx::query("UPDATE mothbus_api_tokens SET last_used_at = ? WHERE id = ?")
+        .bind(now)
+        .bind(token.id)
+        .execute(&state.mysql_pool)
+        .await
+        .context("failed to update api token last use")?;
+
+    sqlx::query("DELETE FROM mothbus_api_token_requests WHERE requested_at < ?")
+        .bind(now - chrono::Duration::days(REQUEST_RETENTION_DAYS))
+        .execute(&state.mysql_pool)
+        .await
+        .context("failed to prune api token requests")?;
+
+    Ok(())
+}
+
+fn hash_token(secret: &str) -> String {
+    crate::relay::bytes_to_hex_display(&sha2::Sha256::digest(secret.as_bytes()))
+}
+
+// Unknown scopes are dropped, so removing a scope from mothbus doesn't break old tokens
+fn parse_scopes(scopes: &str) -> Vec<Scope> {
+    scopes.split(',').filter_map(Scope::from_name).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_round_trip() {
+        assert_eq!(
+            parse_scopes("tickets:read,unknown:scope,polls:read,polls:read_ckeys"),
+            [Scope::TicketsRead, Scope::PollsRead, Scope::PollsReadCkeys]
+        );
+        assert!(parse_scopes("").is_empty());
+
+        for scope in Scope::ALL {
+            assert_eq!(
+                serde_json::to_value(scope).unwrap(),
+                serde_json::json!(scope.name())
+            );
+        }
+    }
+}
@@ -4,13 +4,30 @@ use std::sync::Arc;
 
 use axum::{
     async_trait,
-    extract::{FromRequest, RequestParts},
+    extract::{FromRequest, OriginalUri, RequestParts},
+    middleware::Next,
     response::{IntoResponse, Redirect, Response},
     Extension,
 };
-use http::StatusCode;
+use http::{header::AUTHORIZATION, Request, StatusCode};
+
+use crate::{
+    api_tokens::{self, ApiToken},
+    client_info::ClientInfo,
+    cookies, csrf,
+    session::Session,
+    State,
+};
+
+async fn get_session_jwt<B: Send>(request: &mut RequestParts<B>) -> Option<String> {
+    let cookie_jar = axum_extra::extract::cookie::CookieJar::from_request(request)
+        .await
+        .unwrap();
 
-use crate::{session::Session, State};
+    cookie_jar
+        .get(cookies::SESSION)
+        .map(|session_jwt| session_jwt.value().to_owned())
+}
 
 async fn get_session<B: Send>(
     request: &mut RequestParts<B>,
@@ -19,16 +36,12 @@ async fn get_session<B: Send>(
         .await
         .expect("can't get state");
 
-    let cookie_jar = axum_extra::extract::cookie::CookieJar::from_request(request)
-        .await
-        .unwrap();
-
-    let session_jwt = match cookie_jar.get("session_jwt") {
-        Some(session_jwt) => session_jwt.value(),
+    let session_jwt = match get_session_jwt(request).await {
+        Some(session_jwt) => session_jwt,
         None => return Ok(None),
     };
 
-    state.session(session_jwt).await
+    state.session(&session_jwt).await
 }
 
 async fn get_user<B: Send>(request: &mut RequestParts<B>) -> color_eyre::Result<Option<User>> {
@@ -36,12 +49,113 @@ async fn get_user<B: Send>(request: &mut RequestParts<B>) -> color_eyre::Result<
         .await
         .expect("can't get state");
 
+    if let Some(token) = request.extensions().get::<ApiToken>().cloned() {
+        let creator = Arc::clone(&state).user(&token.created_by).await?;
+        return Ok(Some(User::for_api_token(&token, &creator)));
+    }
+
     let session = match get_session(request).await? {
         Some(session) => session,
         None => return Ok(None),
     };
 
-    Ok(Some(state.user(&session.ckey).await?))
+    let mut user = state.user(&session.ckey).await?;
+    user.csrf_token = get_session_jwt(request)
+        .await
+        .map(|session_jwt| csrf::token_for_session(&session_jwt));
+
+    Ok(Some(user))
+}
+
+/// Lets requests authenticate with `Authorization: Bearer <api token>` instead of a session.
+/// Every request made with a token is recorded.
+pub async fn api_token_layer<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    let secret = match request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|authorization| authorization.to_str().ok())
+        .and_then(|authorization| authorization.strip_prefix("Bearer "))
+    {
+        Some(secret) => secret.trim().to_owned(),
+        None => return next.run(request).await,
+    };
+
+    let state = Arc::clone(
+        request
+            .extensions()
+            .get::<Arc<State>>()
+            .expect("can't get state"),
+    );
+
+    let token = match api_tokens::find_valid(&state.mysql_pool, &secret).await {
+        Ok(Some(token)) => token,
+
+        Ok(None) => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                "invalid, expired, or revoked api token",
+            )
+                .into_response();
+        }
+
+        Err(error) => {
+            tracing::error!("error checking api token: {error:#?}");
+
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "error checking api token, this is a bug. please report to mothblocks",
+            )
+                .into_response();
+        }
+    };
+
+    let method = request.method().to_string();
+    let path = request
+        .uri()
+        .path_and_query()
+        .map(|path_and_query| path_and_query.as_str().to_owned())
+        .unwrap_or_default();
+    let ip = ClientInfo::from_request_ref(&request, &state.config)
+        .ip
+        .to_string();
+
+    request.extensions_mut().insert(token.clone());
+
+    let response = next.run(request).await;
+
+    api_tokens::record_request(
+        &state,
+        &token,
+        &method,
+        &path,
+        response.status().as_u16(),
+        &ip,
+    )
+    .await;
+
+    response
+}
+
+/// The session the request was made with, if it's still valid.
+pub struct CurrentSession(pub Option<Session>);
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for CurrentSession {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        match get_session(request).await {
+            Ok(session_optional) => Ok(Self(session_optional)),
+            Err(error) => {
+                tracing::error!("error getting session (in CurrentSession): {error:#?}");
+
+                Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "error getting session, this is a bug. please report to mothblocks".to_owned(),
+                ))
+            }
+        }
+    }
 }
 
 pub struct AuthenticatedUserOptional(pub Option<User>);
@@ -58,7 +172,7 @@ impl<B: Send> FromRequest<B> for AuthenticatedUserOptional {
 
                 Err((
                     StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("error getting user, this is a bug. please report to mothblocks"),
+                    "error getting user, this is a bug. please report to mothblocks".to_owned(),
                 ))
             }
         }
@@ -76,10 +190,23 @@ impl<B: Send> FromRequest<B> for AuthenticatedUser {
             Ok(Some(user)) => Ok(Self(user)),
 
             Ok(None) => {
-                // TODO: Include a page to go back to
                 tracing::debug!("user not authenticated in AuthenticatedUser, redirecting");
 
-                Err(Redirect::temporary("/login").into_response())
+                let return_to = match OriginalUri::from_request(request).await {
+                    Ok(OriginalUri(uri)) => uri
+                        .path_and_query()
+                        .map(|path_and_query| path_and_query.as_str().to_owned())
+                        .unwrap_or_else(|| "/".to_owned()),
+                    Err(_) => "/".to_owned(),
+                };
+
+                Err(Redirect::temporary(&format!(
+                    "/login?{}",
+                    form_urlencoded::Serializer::new(String::new())
+                        .append_pair("return_to", &return_to)
+                        .finish()
+                ))
+                .into_response())
             }
 
             Err(error) => {
@@ -87,7 +214,7 @@ impl<B: Send> FromRequest<B> for AuthenticatedUser {
 
                 Err((
                     StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("error getting user, this is a bug. please report to mothblocks"),
+                    "error getting user, this is a bug. please report to mothblocks".to_owned(),
                 )
                     .into_response())
             }
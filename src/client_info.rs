@@ -0,0 +1,323 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequest, RequestParts},
+};
+use http::{Extensions, HeaderMap, Request};
+use serde::Deserialize;
+
+use crate::{Config, State};
+
+/// The header trusted proxies put the client's address in. Only that one is read, as proxies
+/// pass the others through untouched, so they could say anything the client wanted.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ForwardedHeader {
+    /// `X-Forwarded-For`, with the scheme from `X-Forwarded-Proto`, as nginx and most others send.
+    #[default]
+    XForwardedFor,
+    /// The standard `Forwarded` header.
+    Forwarded,
+}
+
+/// Who made the request, and how. When the connection comes from a trusted proxy, the
+/// forwarding header it writes is followed back to the first address that isn't one.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ClientInfo {
+    pub ip: IpAddr,
+
+    /// Whether the client reached us over https. Mothbus itself only speaks http,
+    /// so this can only be true behind a trusted proxy.
+    pub https: bool,
+
+    /// Whether the request has forwarding headers that weren't believed,
+    /// such as when it came through a proxy that isn't in `trusted_proxies`.
+    pub untrusted_forwarding: bool,
+}
+
+/// One entry of a forwarding header, describing the connection a proxy received.
+#[derive(Debug, Default)]
+struct Hop {
+    // None when the proxy didn't know or wouldn't say
+    for_ip: Option<IpAddr>,
+    proto: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn resolve(
+        peer: IpAddr,
+        headers: &HeaderMap,
+        trusted_proxies: &[IpAddr],
+        forwarded_header: ForwardedHeader,
+    ) -> Self {
+        let hops = forwarded_hops(headers, forwarded_header);
+        let peer_trusted = trusted_proxies.contains(&peer);
+
+        let mut client_info = Self {
+            ip: peer,
+            https: false,
+            untrusted_forwarding: !peer_trusted
+                && ["forwarded", "x-forwarded-for"]
+                    .into_iter()
+                    .any(|name| headers.contains_key(name)),
+        };
+
+        if !peer_trusted {
+            return client_info;
+        }
+
+        // Each proxy appends who it heard from, so the nearest hops are at the end.
+        // Anything before the first untrusted address could have been made up by the client.
+        for hop in hops.into_iter().rev() {
+            if let Some(proto) = hop.proto {
+                client_info.https = proto.eq_ignore_ascii_case("https");
+            }
+
+            let Some(for_ip) = hop.for_ip else {
+                break;
+            };
+
+            client_info.ip = for_ip;
+            if !trusted_proxies.contains(&for_ip) {
+                break;
+            }
+        }
+
+        client_info
+    }
+
+    /// For middleware, which sees the request before extractors can run.
+    pub fn from_request_ref<B>(request: &Request<B>, config: &Config) -> Self {
+        Self::resolve(
+            peer(request.extensions()),
+            request.headers(),
+            &config.trusted_proxies,
+            config.forwarded_header,
+        )
+    }
+
+    /// Whether the request came from this machine, and not through a proxy on it.
+    pub fn is_local(&self) -> bool {
+        self.ip.is_loopback() && !self.untrusted_forwarding
+    }
+}
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let state = request
+            .extensions()
+            .get::<Arc<State>>()
+            .expect("can't get state");
+
+        Ok(Self::resolve(
+            peer(request.extensions()),
+            request.headers(),
+            &state.config.trusted_proxies,
+            state.config.forwarded_header,
+        ))
+    }
+}
+
+// Always there when served with `into_make_service_with_connect_info`, as mothbus is
+fn peer(extensions: &Extensions) -> IpAddr {
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+        .unwrap_or(Ipv4Addr::UNSPECIFIED.into())
+}
+
+/// Only ever the header the trusted proxy writes, and never whatever else the client sent along.
+fn forwarded_hops(headers: &HeaderMap, forwarded_header: ForwardedHeader) -> Vec<Hop> {
+    let values = |name: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .collect::<Vec<_>>()
+    };
+
+    if forwarded_header == ForwardedHeader::Forwarded {
+        return values("forwarded")
+            .into_iter()
+            .map(|element| {
+                let mut hop = Hop::default();
+
+                for pair in element.split(';') {
+                    let Some((key, value)) = pair.split_once('=') else {
+                        continue;
+                    };
+
+                    let value = value.trim().trim_matches('"');
+
+                    match key.trim().to_ascii_lowercase().as_str() {
+                        "for" => hop.for_ip = parse_node(value),
+                        "proto" => hop.proto = Some(value.to_owned()),
+                        _ => {}
+                    }
+                }
+
+                hop
+            })
+            .collect();
+    }
+
+    let mut hops: Vec<Hop> = values("x-forwarded-for")
+        .into_iter()
+        .map(|for_ip| Hop {
+            for_ip: parse_node(for_ip),
+            proto: None,
+        })
+        .collect();
+
+    // Lined up from the end, as each proxy appends to both
+    for (hop, proto) in hops
+        .iter_mut()
+        .rev()
+        .zip(values("x-forwarded-proto").into_iter().rev())
+    {
+        hop.proto = Some(proto.to_owned());
+    }
+
+    hops
+}
+
+/// Addresses can come with ports, and IPv6 ones in brackets, such as `[2001:db8::1]:4711`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+
+    node.strip_prefix('[')
+        .and_then(|node| node.strip_suffix(']'))
+        .and_then(|ip| ip.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    fn header_map(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for &(name, value) in pairs {
+            headers.append(name, HeaderValue::from_static(value));
+        }
+
+        headers
+    }
+
+    use ForwardedHeader::{Forwarded, XForwardedFor};
+
+    #[test]
+    fn forwarded_for_only_from_trusted_proxies() {
+        let proxy: IpAddr = "127.0.0.1".parse().unwrap();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+
+        let headers = header_map(&[
+            ("x-forwarded-for", "198.51.100.1, 203.0.113.7"),
+            ("x-forwarded-proto", "https"),
+        ]);
+
+        assert_eq!(
+            ClientInfo::resolve(proxy, &headers, &[proxy], XForwardedFor),
+            ClientInfo {
+                ip: client,
+                https: true,
+                untrusted_forwarding: false,
+            }
+        );
+
+        // Without a trusted proxy, the headers are whatever the client wanted them to be
+        let untrusted = ClientInfo::resolve(proxy, &headers, &[], XForwardedFor);
+        assert_eq!(untrusted.ip, proxy);
+        assert!(!untrusted.https);
+        assert!(!untrusted.is_local());
+
+        assert_eq!(
+            ClientInfo::resolve(client, &headers, &[proxy], XForwardedFor).ip,
+            client
+        );
+
+        let direct = ClientInfo::resolve(proxy, &HeaderMap::new(), &[proxy], XForwardedFor);
+        assert_eq!(direct.ip, proxy);
+        assert!(direct.is_local());
+
+        let nonsense = header_map(&[("x-forwarded-for", "nonsense")]);
+        assert_eq!(
+            ClientInfo::resolve(proxy, &nonsense, &[proxy], XForwardedFor).ip,
+            proxy
+        );
+    }
+
+    #[test]
+    fn standard_forwarded_header() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let inner_proxy: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let headers = header_map(&[
+            (
+                "forwarded",
+                r#"for=198.51.100.1;proto=http, for="[2001:db8::1]:4711";proto=https"#,
+            ),
+            ("forwarded", "for=10.0.0.2;proto=http"),
+            // Ignored, as the proxy was configured to write Forwarded
+            ("x-forwarded-for", "192.0.2.1"),
+        ]);
+
+        let client_info = ClientInfo::resolve(proxy, &headers, &[proxy, inner_proxy], Forwarded);
+        assert_eq!(client_info.ip, "2001:db8::1".parse::<IpAddr>().unwrap());
+        assert!(client_info.https);
+
+        let obfuscated = header_map(&[("forwarded", "for=_hidden;proto=https")]);
+        let client_info = ClientInfo::resolve(proxy, &obfuscated, &[proxy], Forwarded);
+        assert_eq!(client_info.ip, proxy);
+        assert!(client_info.https);
+    }
+
+    #[test]
+    fn client_sent_forwarded_is_ignored() {
+        let proxy: IpAddr = "127.0.0.1".parse().unwrap();
+
+        // nginx appends to X-Forwarded-For, and passes the client's own Forwarded header through
+        for forwarded in [
+            "for=127.0.0.1",
+            "for=_hidden",
+            "for=198.51.100.1;proto=https",
+        ] {
+            let mut headers = header_map(&[("x-forwarded-for", "203.0.113.7")]);
+            headers.insert("forwarded", HeaderValue::from_static(forwarded));
+
+            let client_info = ClientInfo::resolve(proxy, &headers, &[proxy], XForwardedFor);
+            assert_eq!(client_info.ip, "203.0.113.7".parse::<IpAddr>().unwrap());
+            assert!(!client_info.https);
+            assert!(!client_info.is_local());
+        }
+
+        // Straight to mothbus, a Forwarded header doesn't make a client local either
+        let headers = header_map(&[("forwarded", "for=127.0.0.1")]);
+        let client_info = ClientInfo::resolve(
+            "203.0.113.7".parse().unwrap(),
+            &headers,
+            &[proxy],
+            XForwardedFor,
+        );
+        assert!(!client_info.ip.is_loopback());
+        assert!(client_info.untrusted_forwarding);
+    }
+}
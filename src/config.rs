@@ -1,20 +1,68 @@
-use std::{io::Read, net::IpAddr};
+use std::{collections::HashMap, io::Read, net::IpAddr};
 
 use serde::Deserialize;
 
+use crate::{
+    client_info::ForwardedHeader,
+    rate_limit::RateLimits,
+    relay::{HookKind, Verification},
+    rights::Permissions,
+    security_headers::SecurityHeaders,
+    urls::UrlTemplates,
+};
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct Config {
     pub address: IpAddr,
     pub db_url: String,
     pub port: u16,
 
+    /// The schema the game's feedback tables live in.
+    #[serde(default = "default_db_schema")]
+    pub db_schema: String,
+
     #[serde(default)]
     pub mock_login: bool,
 
+    /// Reverse proxies whose forwarding header is believed,
+    /// for the address and scheme players really connected with.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+
+    /// The header the trusted proxies write. Any other forwarding header is ignored.
+    #[serde(default)]
+    pub forwarded_header: ForwardedHeader,
+
+    /// How many requests each player or address can make to the expensive routes.
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+
+    /// The Content-Security-Policy and other headers sent with every response.
+    #[serde(default)]
+    pub security_headers: SecurityHeaders,
+
+    /// Logging in through the tgstation forums.
     pub oauth2: OAuth2Options,
 
+    /// Logging in through Discord, for players who have linked their account in game.
+    pub discord_oauth2: Option<OAuth2Options>,
+
+    /// Rotates the jwt signing key after this many days. Can also be done with `mothbus rotate-jwt-key`.
+    pub jwt_key_rotation_days: Option<u32>,
+
     pub github_webhook: GithubWebhookOptions,
 
+    /// Other services relayed to Discord through `/hooks/<name>`.
+    #[serde(default)]
+    pub hooks: HashMap<String, HookOptions>,
+
+    #[serde(default)]
+    pub urls: UrlTemplates,
+
+    /// The admin rights needed for each capability.
+    #[serde(default)]
+    pub permissions: Permissions,
+
     #[serde(default)]
     pub evasion_masters: Vec<String>,
 }
@@ -24,12 +72,121 @@ pub struct OAuth2Options {
     pub client_id: String,
     pub client_secret: String,
     pub redirect_uri: String,
+
+    /// Where the provider is, when not the real one, such as a local stand-in.
+    pub authorize_url: Option<String>,
+    pub token_url: Option<String>,
+    pub user_url: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(test, derive(Default))]
 pub struct GithubWebhookOptions {
     pub secret: String,
+
+    /// Receives every event that no route matched.
+    pub discord_url: Option<String>,
+
+    #[serde(default)]
+    pub routes: Vec<GithubWebhookRoute>,
+
+    /// How issue and pull request bodies are summarised, keyed by repository full name.
+    /// Repositories not listed here use the tgstation templates.
+    #[serde(default)]
+    pub summaries: HashMap<String, RepositorySummaries>,
+
+    /// The repository that test merges are made from, used to look up pull requests.
+    #[serde(default = "default_test_merge_repository")]
+    pub test_merge_repository: String,
+}
+
+fn default_db_schema() -> String {
+    "tgstation13".to_owned()
+}
+
+/// Sends matching events to a Discord webhook. Empty lists match anything.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct GithubWebhookRoute {
     pub discord_url: String,
+
+    /// Role ID to ping when this route is used.
+    pub mention_role: Option<String>,
+
+    /// Repository full names, such as `tgstation/tgstation`.
+    #[serde(default)]
+    pub repositories: Vec<String>,
+
+    /// Values of `X-GitHub-Event`, such as `issues` or `pull_request`.
+    #[serde(default)]
+    pub events: Vec<String>,
+
+    #[serde(default)]
+    pub actions: Vec<String>,
+
+    /// Matches if the issue or pull request has any of these labels.
+    #[serde(default)]
+    pub labels: Vec<String>,
+
+    #[serde(default)]
+    pub authors: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct RepositorySummaries {
+    pub issues: SummaryRules,
+    pub pull_requests: SummaryRules,
+}
+
+impl Default for RepositorySummaries {
+    fn default() -> Self {
+        Self {
+            issues: SummaryRules {
+                sections: vec!["## Issue Summary".to_owned(), "## Reproduction:".to_owned()],
+                ..Default::default()
+            },
+
+            pull_requests: SummaryRules {
+                sections: vec!["## About The Pull Request".to_owned()],
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SummaryRules {
+    /// Headers to start the summary from, the first one found wins.
+    /// If none are found, the summary starts from the top of the body.
+    pub sections: Vec<String>,
+    pub max_lines: usize,
+    pub max_characters: usize,
+    /// Use the first image as the embed's thumbnail, rather than dropping it.
+    pub image_thumbnails: bool,
+}
+
+impl Default for SummaryRules {
+    fn default() -> Self {
+        Self {
+            sections: Vec::new(),
+            max_lines: 4,
+            max_characters: 500,
+            image_thumbnails: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct HookOptions {
+    pub kind: HookKind,
+    pub verification: Verification,
+    pub discord_url: String,
+    pub mention_role: Option<String>,
+}
+
+fn default_test_merge_repository() -> String {
+    "tgstation/tgstation".to_owned()
 }
 
 impl Config {
@@ -0,0 +1,155 @@
+//! Every cookie mothbus sets, so that they all get the same attributes.
+
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+
+use crate::{client_info::ClientInfo, session};
+
+pub const SESSION: &str = "session_jwt";
+pub const OAUTH_NONCE: &str = "oauth_nonce";
+pub const CREATED_API_TOKEN: &str = "created_api_token";
+
+/// Where the nonce is needed, so it isn't sent with anything else.
+const OAUTH_NONCE_PATH: &str = "/oauth";
+
+/// Only the token list shows a new token, and only once.
+const CREATED_API_TOKEN_PATH: &str = "/admin/api-tokens";
+
+pub fn add_session(
+    cookie_jar: CookieJar,
+    session_jwt: String,
+    client_info: ClientInfo,
+) -> CookieJar {
+    cookie_jar.add(cookie(
+        SESSION,
+        session_jwt,
+        "/",
+        time::Duration::days(session::SESSION_LIFETIME_DAYS),
+        client_info,
+    ))
+}
+
+pub fn remove_session(cookie_jar: CookieJar) -> CookieJar {
+    cookie_jar.remove(Cookie::build(SESSION, "").path("/").finish())
+}
+
+pub fn add_oauth_nonce(cookie_jar: CookieJar, nonce: String, client_info: ClientInfo) -> CookieJar {
+    cookie_jar.add(cookie(
+        OAUTH_NONCE,
+        nonce,
+        OAUTH_NONCE_PATH,
+        time::Duration::seconds(session::OAUTH_STATE_LIFETIME.as_secs() as i64),
+        client_info,
+    ))
+}
+
+pub fn remove_oauth_nonce(cookie_jar: CookieJar) -> CookieJar {
+    cookie_jar.remove(
+        Cookie::build(OAUTH_NONCE, "")
+            .path(OAUTH_NONCE_PATH)
+            .finish(),
+    )
+}
+
+/// Carries a new token's secret across the redirect after creating it, so that refreshing
+/// the page it's shown on doesn't create another.
+pub fn add_created_api_token(
+    cookie_jar: CookieJar,
+    secret: String,
+    client_info: ClientInfo,
+) -> CookieJar {
+    cookie_jar.add(cookie(
+        CREATED_API_TOKEN,
+        secret,
+        CREATED_API_TOKEN_PATH,
+        time::Duration::minutes(1),
+        client_info,
+    ))
+}
+
+pub fn remove_created_api_token(cookie_jar: CookieJar) -> CookieJar {
+    cookie_jar.remove(
+        Cookie::build(CREATED_API_TOKEN, "")
+            .path(CREATED_API_TOKEN_PATH)
+            .finish(),
+    )
+}
+
+fn cookie(
+    name: &'static str,
+    value: String,
+    path: &'static str,
+    max_age: time::Duration,
+    client_info: ClientInfo,
+) -> Cookie<'static> {
+    Cookie::build(name, value)
+        .path(path)
+        .http_only(true)
+        // Lax rather than strict, so that following a link to mothbus from elsewhere,
+        // or coming back from logging in, still sends the cookie
+        .same_site(SameSite::Lax)
+        // Some browsers won't keep secure cookies over plain http, even on localhost
+        .secure(client_info.https || !client_info.is_local())
+        .max_age(max_age)
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::{FromRequest, RequestParts};
+    use http::Request;
+
+    use super::*;
+
+    // The jar can only be made from a request
+    async fn empty_jar() -> CookieJar {
+        CookieJar::from_request(&mut RequestParts::new(Request::new(())))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn session_cookie_attributes() {
+        let behind_proxy = ClientInfo {
+            ip: "203.0.113.7".parse().unwrap(),
+            https: true,
+            untrusted_forwarding: false,
+        };
+
+        let cookie_jar = add_session(empty_jar().await, "jwt".to_owned(), behind_proxy);
+        let cookie = cookie_jar.get(SESSION).unwrap();
+
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.secure(), Some(true));
+        assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+        assert_eq!(cookie.path(), Some("/"));
+
+        let local = ClientInfo {
+            ip: "127.0.0.1".parse().unwrap(),
+            https: false,
+            untrusted_forwarding: false,
+        };
+
+        let cookie_jar = add_session(empty_jar().await, "jwt".to_owned(), local);
+        assert_eq!(cookie_jar.get(SESSION).unwrap().secure(), Some(false));
+    }
+
+    #[tokio::test]
+    async fn created_api_token_stays_on_its_page() {
+        let local = ClientInfo {
+            ip: "127.0.0.1".parse().unwrap(),
+            https: false,
+            untrusted_forwarding: false,
+        };
+
+        let cookie_jar =
+            add_created_api_token(empty_jar().await, "mothbus_secret".to_owned(), local);
+        let cookie = cookie_jar.get(CREATED_API_TOKEN).unwrap();
+
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.path(), Some("/admin/api-tokens"));
+        assert_eq!(cookie.max_age(), Some(time::Duration::minutes(1)));
+
+        let cookie_jar = remove_created_api_token(cookie_jar);
+        assert!(cookie_jar.get(CREATED_API_TOKEN).is_none());
+    }
+}
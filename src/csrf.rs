@@ -0,0 +1,190 @@
+//! Forms that change anything send back a token tied to the session, so that other sites
+//! can't submit them on a player's behalf.
+
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    body::{Bytes, HttpBody},
+    extract::{FromRequest, RequestParts},
+    response::{IntoResponse, Response},
+    BoxError, Extension,
+};
+use axum_extra::extract::cookie::CookieJar;
+use hmac::{Hmac, Mac};
+use http::StatusCode;
+use serde::de::DeserializeOwned;
+
+use crate::{cookies, routes::errors::make_forbidden, State};
+
+/// The name of the hidden field forms put the token in.
+pub const FIELD: &str = "csrf_token";
+
+/// Derived from the session cookie, which pages can't read, so it needs no storage of its own.
+pub fn token_for_session(session_jwt: &str) -> String {
+    let mut hmac: Hmac<sha2::Sha256> =
+        Hmac::new_from_slice(session_jwt.as_bytes()).expect("failed to create hmac");
+    hmac.update(b"csrf");
+
+    crate::relay::bytes_to_hex_display(&hmac.finalize().into_bytes())
+}
+
+/// A form that must have a valid CSRF token. Use in place of `Form`.
+#[derive(Debug)]
+pub struct CsrfForm<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for CsrfForm<T>
+where
+    T: DeserializeOwned,
+    B: HttpBody + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let body = verified_body(request).await?;
+
+        serde_urlencoded::from_bytes(&body)
+            .map(Self)
+            .map_err(|error| {
+                (StatusCode::BAD_REQUEST, format!("invalid form: {error}")).into_response()
+            })
+    }
+}
+
+/// For forms with nothing in them but the CSRF token, such as a single button.
+#[derive(Debug)]
+pub struct CsrfProtected;
+
+#[async_trait]
+impl<B> FromRequest<B> for CsrfProtected
+where
+    B: HttpBody + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        verified_body(request).await.map(|_| Self)
+    }
+}
+
+async fn verified_body<B>(request: &mut RequestParts<B>) -> Result<Bytes, Response>
+where
+    B: HttpBody + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    let Extension(state) = Extension::<Arc<State>>::from_request(request)
+        .await
+        .expect("can't get state");
+
+    let cookie_jar = CookieJar::from_request(request).await.unwrap();
+
+    let body = Bytes::from_request(request)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    let given = form_urlencoded::parse(&body)
+        .find(|(key, _)| key == FIELD)
+        .map(|(_, value)| value);
+
+    let expected = cookie_jar
+        .get(cookies::SESSION)
+        .map(|session_jwt| token_for_session(session_jwt.value()));
+
+    match (given, expected) {
+        (Some(given), Some(expected))
+            if constant_time_eq(given.as_bytes(), expected.as_bytes()) =>
+        {
+            Ok(body)
+        }
+
+        _ => {
+            tracing::debug!("rejected form with a missing or wrong csrf token");
+
+            Err(make_forbidden(
+                state,
+                "This form has expired. Go back, reload the page, and try again.",
+            )
+            .await
+            .into_response())
+        }
+    }
+}
+
+// So that how long the comparison takes doesn't give away how much of the token was right
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, routing::post, Router};
+    use http::{header, Request};
+    use serde::Deserialize;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct RenameForm {
+        name: String,
+    }
+
+    #[tokio::test]
+    async fn forms_need_the_session_token() {
+        let state = Arc::new(State::for_tests(State::test_config("")));
+
+        let app = Router::new()
+            .route(
+                "/rename",
+                post(|CsrfForm(form): CsrfForm<RenameForm>| async move { form.name }),
+            )
+            .layer(Extension(state));
+
+        let request = |cookie: Option<&str>, body: String| {
+            let mut request = Request::post("/rename")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+
+            if let Some(cookie) = cookie {
+                request = request.header(header::COOKIE, cookie);
+            }
+
+            app.clone().oneshot(request.body(Body::from(body)).unwrap())
+        };
+
+        let token = token_for_session("session-a");
+        assert_ne!(token, token_for_session("session-b"));
+
+        let response = request(
+            Some("session_jwt=session-a"),
+            format!("name=moth&{FIELD}={token}"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        for (cookie, body) in [
+            // Another session's token
+            (
+                Some("session_jwt=session-b"),
+                format!("name=moth&{FIELD}={token}"),
+            ),
+            (Some("session_jwt=session-a"), "name=moth".to_owned()),
+            (None, format!("name=moth&{FIELD}={token}")),
+        ] {
+            assert_eq!(
+                request(cookie, body).await.unwrap().status(),
+                StatusCode::FORBIDDEN
+            );
+        }
+    }
+}
@@ -0,0 +1,390 @@
+use std::{sync::Arc, time::Duration};
+
+use color_eyre::eyre::Context;
+use serde::Serialize;
+use sqlx::Row;
+
+use crate::State;
+
+const MAX_ATTEMPTS: u32 = 8;
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const BASE_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+const STATUS_PENDING: &str = "pending";
+const STATUS_FAILED: &str = "failed";
+
+/// Every message sent to Discord goes through here, so that failures and rate limits
+/// are retried rather than lost.
+#[tracing::instrument(skip(state, payload))]
+pub async fn enqueue(
+    state: &State,
+    discord_url: &str,
+    payload: &serde_json::Value,
+) -> color_eyre::Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+
+    sqlx::query(
+        "INSERT INTO mothbus_discord_deliveries
+            (discord_url, payload, status, created_at, next_attempt_at)
+        VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(discord_url)
+    .bind(payload.to_string())
+    .bind(STATUS_PENDING)
+    .bind(now)
+    .bind(now)
+    .execute(&state.mysql_pool)
+    .await
+    .context("failed to queue discord delivery")?;
+
+    state.discord_queue_notify.notify_one();
+
+    Ok(())
+}
+
+pub fn discord_message(embed: &serde_json::Value, mention_roles: &[&str]) -> serde_json::Value {
+    if mention_roles.is_empty() {
+        return serde_json::json!({
+            "embeds": [embed],
+        });
+    }
+
+    serde_json::json!({
+        "content": mention_roles
+            .iter()
+            .map(|role| format!("<@&{role}>"))
+            .collect::<Vec<_>>()
+            .join(" "),
+        "allowed_mentions": {
+            "roles": mention_roles,
+        },
+        "embeds": [embed],
+    })
+}
+
+pub async fn run_worker(state: Arc<State>) {
+    loop {
+        if let Err(error) = deliver_due(&state).await {
+            tracing::error!("failed to deliver discord messages: {error:#}");
+        }
+
+        tokio::select! {
+            _ = state.discord_queue_notify.notified() => {}
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum DeliveryResult {
+    Delivered,
+    RateLimited(Duration),
+    /// Worth trying again, such as when Discord is down.
+    Failed(String),
+    /// Discord won't take it however many times it's sent, such as when the webhook was deleted.
+    Rejected(String),
+}
+
+async fn deliver_due(state: &State) -> color_eyre::Result<()> {
+    let rows = sqlx::query(
+        "SELECT id, discord_url, payload, attempts
+        FROM mothbus_discord_deliveries
+        WHERE status = ? AND next_attempt_at <= ?
+        ORDER BY id
+        LIMIT 10",
+    )
+    .bind(STATUS_PENDING)
+    .bind(chrono::Utc::now().naive_utc())
+    .fetch_all(&state.mysql_pool)
+    .await
+    .context("failed to fetch pending discord deliveries")?;
+
+    let client = reqwest::Client::new();
+
+    for row in rows {
+        let id: u64 = row.try_get("id")?;
+        let discord_url: String = row.try_get("discord_url")?;
+        let payload: String = row.try_get("payload")?;
+        let attempts: u32 = row.try_get("attempts")?;
+
+        match deliver(&client, &discord_url, payload).await {
+            DeliveryResult::Delivered => {
+                sqlx::query("DELETE FROM mothbus_discord_deliveries WHERE id = ?")
+                    .bind(id)
+                    .execute(&state.mysql_pool)
+                    .await
+                    .context("failed to remove delivered discord message")?;
+            }
+
+            DeliveryResult::RateLimited(retry_after) => {
+                tracing::debug!("discord delivery {id} rate limited for {retry_after:?}");
+
+                // Waiting out a rate limit isn't the message's fault, so it isn't an attempt
+                reschedule(state, id, attempts, retry_after, "rate limited").await?;
+
+                // Everything else in this batch is likely to hit the same limit
+                break;
+            }
+
+            DeliveryResult::Failed(error) => {
+                let attempts = attempts + 1;
+                tracing::warn!("discord delivery {id} failed (attempt {attempts}): {error}");
+
+                if attempts >= MAX_ATTEMPTS {
+                    mark_failed(state, id, attempts, &error).await?;
+                } else {
+                    reschedule(state, id, attempts, backoff(attempts), &error).await?;
+                }
+            }
+
+            DeliveryResult::Rejected(error) => {
+                tracing::warn!("discord delivery {id} was rejected: {error}");
+                mark_failed(state, id, attempts + 1, &error).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn deliver(client: &reqwest::Client, discord_url: &str, payload: String) -> DeliveryResult {
+    let response = match client
+        .post(discord_url)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(payload)
+        .send()
+        .await
+    {
+        Ok(response) => response,
+        Err(error) => return DeliveryResult::Failed(format!("{error:#}")),
+    };
+
+    let status = response.status();
+
+    if status.is_success() {
+        return DeliveryResult::Delivered;
+    }
+
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        let retry_after_header = response
+            .headers()
+            .get(http::header::RETRY_AFTER)
+            .and_then(|retry_after| retry_after.to_str().ok())
+            .and_then(|retry_after| retry_after.parse::<f64>().ok());
+
+        let retry_after_body = response
+            .json::<serde_json::Value>()
+            .await
+            .ok()
+            .and_then(|body| body["retry_after"].as_f64());
+
+        return DeliveryResult::RateLimited(retry_after(retry_after_body.or(retry_after_header)));
+    }
+
+    let error = format!("{status}: {}", response.text().await.unwrap_or_default());
+
+    if status.is_client_error() {
+        DeliveryResult::Rejected(error)
+    } else {
+        DeliveryResult::Failed(error)
+    }
+}
+
+/// Clamped, as whatever Discord sends back shouldn't be able to stop deliveries for good.
+fn retry_after(seconds: Option<f64>) -> Duration {
+    Duration::from_secs_f64(
+        seconds
+            .filter(|seconds| !seconds.is_nan())
+            .unwrap_or(BASE_BACKOFF.as_secs_f64())
+            .clamp(0.0, MAX_BACKOFF.as_secs_f64()),
+    )
+}
+
+fn backoff(attempts: u32) -> Duration {
+    BASE_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+async fn mark_failed(state: &State, id: u64, attempts: u32, error: &str) -> color_eyre::Result<()> {
+    sqlx::query(
+        "UPDATE mothbus_discord_deliveries
+        SET status = ?, attempts = ?, last_error = ?
+        WHERE id = ?",
+    )
+    .bind(STATUS_FAILED)
+    .bind(attempts)
+    .bind(error)
+    .bind(id)
+    .execute(&state.mysql_pool)
+    .await
+    .context("failed to mark discord delivery as failed")?;
+
+    Ok(())
+}
+
+async fn reschedule(
+    state: &State,
+    id: u64,
+    attempts: u32,
+    delay: Duration,
+    error: &str,
+) -> color_eyre::Result<()> {
+    let next_attempt_at = chrono::Utc::now().naive_utc()
+        + chrono::Duration::from_std(delay).context("retry delay out of range")?;
+
+    sqlx::query(
+        "UPDATE mothbus_discord_deliveries
+        SET attempts = ?, last_error = ?, next_attempt_at = ?
+        WHERE id = ?",
+    )
+    .bind(attempts)
+    .bind(error)
+    .bind(next_attempt_at)
+    .bind(id)
+    .execute(&state.mysql_pool)
+    .await
+    .context("failed to reschedule discord delivery")?;
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailedDelivery {
+    pub id: u64,
+    pub discord_url: String,
+    pub title: Option<String>,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[tracing::instrument(skip(mysql_pool))]
+pub async fn failed_deliveries(
+    mysql_pool: &sqlx::MySqlPool,
+) -> color_eyre::Result<Vec<FailedDelivery>> {
+    let rows = sqlx::query(
+        "SELECT id, discord_url, payload, attempts, last_error, created_at
+        FROM mothbus_discord_deliveries
+        WHERE status = ?
+        ORDER BY id DESC
+        LIMIT 100",
+    )
+    .bind(STATUS_FAILED)
+    .fetch_all(mysql_pool)
+    .await
+    .context("failed to fetch failed discord deliveries")?;
+
+    rows.into_iter()
+        .map(|row| {
+            let payload: serde_json::Value =
+                serde_json::from_str(&row.try_get::<String, _>("payload")?).unwrap_or_default();
+            let discord_url: String = row.try_get("discord_url")?;
+
+            Ok(FailedDelivery {
+                id: row.try_get("id")?,
+                discord_url: redact_discord_url(&discord_url),
+                title: payload["embeds"][0]["title"].as_str().map(str::to_owned),
+                attempts: row.try_get("attempts")?,
+                last_error: row.try_get("last_error")?,
+                created_at: row.try_get("created_at")?,
+            })
+        })
+        .collect()
+}
+
+/// Puts a failed delivery back in the queue. Returns false if there was no such failed delivery.
+#[tracing::instrument(skip(state))]
+pub async fn replay(state: &State, id: u64) -> color_eyre::Result<bool> {
+    let result = sqlx::query(
+        "UPDATE mothbus_discord_deliveries
+        SET status = ?, attempts = 0, next_attempt_at = ?
+        WHERE id = ? AND status = ?",
+    )
+    .bind(STATUS_PENDING)
+    .bind(chrono::Utc::now().naive_utc())
+    .bind(id)
+    .bind(STATUS_FAILED)
+    .execute(&state.mysql_pool)
+    .await
+    .context("failed to replay discord delivery")?;
+
+    state.discord_queue_notify.notify_one();
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Webhook URLs end with their token, which shouldn't be shown even to admins
+fn redact_discord_url(discord_url: &str) -> String {
+    match discord_url.rsplit_once('/') {
+        Some((without_token, _)) => format!("{without_token}/..."),
+        None => discord_url.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::Path;
+
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_and_caps() {
+        assert_eq!(backoff(1), Duration::from_secs(5));
+        assert_eq!(backoff(2), Duration::from_secs(10));
+        assert_eq!(backoff(4), Duration::from_secs(40));
+        assert_eq!(backoff(100), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn retry_after_is_clamped() {
+        assert_eq!(retry_after(Some(1.5)), Duration::from_millis(1500));
+        assert_eq!(retry_after(None), BASE_BACKOFF);
+        assert_eq!(retry_after(Some(-1.0)), Duration::ZERO);
+
+        for seconds in [f64::INFINITY, f64::NAN, 1e300] {
+            assert!(retry_after(Some(seconds)) <= MAX_BACKOFF, "{seconds}");
+        }
+    }
+
+    #[tokio::test]
+    async fn only_some_failures_are_retried() {
+        let app = axum::Router::new().route(
+            "/:status",
+            axum::routing::post(|Path(status): Path<u16>| async move {
+                let status = http::StatusCode::from_u16(status).unwrap();
+                (status, [(http::header::RETRY_AFTER, "inf")], "no")
+            }),
+        );
+
+        let server = axum::Server::bind(&([127, 0, 0, 1], 0).into()).serve(app.into_make_service());
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        let client = reqwest::Client::new();
+        let send = |status: u16| {
+            let (client, url) = (&client, format!("{url}/{status}"));
+            async move { deliver(client, &url, "{}".to_owned()).await }
+        };
+
+        assert_eq!(send(204).await, DeliveryResult::Delivered);
+        assert_eq!(send(429).await, DeliveryResult::RateLimited(MAX_BACKOFF));
+        assert!(matches!(send(500).await, DeliveryResult::Failed(_)));
+
+        for status in [400, 401, 403, 404] {
+            assert!(
+                matches!(send(status).await, DeliveryResult::Rejected(_)),
+                "{status}"
+            );
+        }
+    }
+
+    #[test]
+    fn redact_discord_url_token() {
+        assert_eq!(
+            redact_discord_url("https://discord.com/api/webhooks/1234/abcd"),
+            "https://discord.com/api/webhooks/1234/..."
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use color_eyre::eyre::Context;
+use moka::future::Cache;
+use serde::Serialize;
+use sqlx::{mysql::MySqlRow, Row};
+
+use crate::State;
+
+/// Payloads bigger than this are cut off, and can't be replayed.
+const MAX_PAYLOAD_LENGTH: usize = 256 * 1024;
+/// Anyone can send deliveries with a bad signature, so only enough to see what they were is kept.
+const MAX_UNVERIFIED_PAYLOAD_LENGTH: usize = 1024;
+const RETENTION_DAYS: i64 = 30;
+
+/// How long a delivery id is remembered for. GitHub stops retrying well before this.
+const DEDUPLICATION_TTL: Duration = Duration::from_secs(60 * 60 * 24);
+const DEDUPLICATION_CAPACITY: u64 = 10_000;
+
+/// Remembers which deliveries have been handled, so that retries and manual redeliveries
+/// from GitHub don't post to Discord twice. Seeded from the delivery log on startup.
+#[derive(Debug)]
+pub struct DeliveryDeduplicator {
+    seen: Cache<String, ()>,
+    duplicates: AtomicU64,
+}
+
+impl DeliveryDeduplicator {
+    pub fn empty() -> Self {
+        Self {
+            seen: Cache::builder()
+                .max_capacity(DEDUPLICATION_CAPACITY)
+                .time_to_live(DEDUPLICATION_TTL)
+                .build(),
+            duplicates: AtomicU64::new(0),
+        }
+    }
+
+    pub async fn load(mysql_pool: &sqlx::MySqlPool) -> color_eyre::Result<Self> {
+        let deduplicator = Self::empty();
+
+        let delivery_ids: Vec<String> = sqlx::query_scalar(
+            "SELECT DISTINCT delivery_id
+            FROM mothbus_github_deliveries
+            WHERE signature_valid
+                AND replay_of IS NULL
+                AND delivery_id != ''
+                AND outcome NOT LIKE 'failed%'
+                AND received_at > ?",
+        )
+        .bind(chrono::Utc::now().naive_utc() - chrono::Duration::from_std(DEDUPLICATION_TTL)?)
+        .fetch_all(mysql_pool)
+        .await
+        .context("failed to load recent github delivery ids")?;
+
+        tracing::debug!("loaded {} recent github delivery ids", delivery_ids.len());
+
+        for delivery_id in delivery_ids {
+            deduplicator.seen.insert(delivery_id, ()).await;
+        }
+
+        Ok(deduplicator)
+    }
+
+    /// Returns false if the delivery has already been claimed, in which case it's counted as a duplicate.
+    pub async fn claim(&self, delivery_id: &str) -> bool {
+        // Without an id there's nothing to deduplicate on
+        if delivery_id.is_empty() {
+            return true;
+        }
+
+        if self
+            .seen
+            .entry_by_ref(delivery_id)
+            .or_insert(())
+            .await
+            .is_fresh()
+        {
+            true
+        } else {
+            self.duplicates.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+
+    /// Lets a delivery that failed to be handled through again, so GitHub's retry isn't dropped.
+    pub async fn release(&self, delivery_id: &str) {
+        self.seen.invalidate(delivery_id).await;
+    }
+
+    /// The number of duplicates seen since startup.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+}
+
+pub struct NewDelivery<'a> {
+    pub delivery_id: &'a str,
+    pub event_type: &'a str,
+    pub webhook_body: &'a serde_json::Value,
+    pub payload: &'a [u8],
+    pub signature_valid: bool,
+    pub outcome: String,
+    pub replay_of: Option<u64>,
+}
+
+/// Records an incoming delivery. Failing to do so is logged rather than failing the webhook.
+#[tracing::instrument(skip_all)]
+pub async fn record(state: &State, delivery: &NewDelivery<'_>) {
+    if let Err(error) = try_record(state, delivery).await {
+        tracing::error!("failed to record github delivery: {error:#}");
+    }
+}
+
+async fn try_record(state: &State, delivery: &NewDelivery<'_>) -> color_eyre::Result<()> {
+    let (payload, payload_truncated) = stored_payload(delivery.payload, delivery.signature_valid);
+
+    let now = chrono::Utc::now().naive_utc();
+
+    sqlx::query(
+        "INSERT INTO mothbus_github_deliveries
+            (delivery_id, event, action, repository, signature_valid, outcome, payload, payload_truncated, received_at, replay_of)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(delivery.delivery_id)
+    .bind(delivery.event_type)
+    .bind(delivery.webhook_body["action"].as_str())
+    .bind(delivery.webhook_body["repository"]["full_name"].as_str())
+    .bind(delivery.signature_valid)
+    .bind(&delivery.outcome)
+    .bind(payload)
+    .bind(payload_truncated)
+    .bind(now)
+    .bind(delivery.replay_of)
+    .execute(&state.mysql_pool)
+    .await
+    .context("failed to insert github delivery")?;
+
+    sqlx::query("DELETE FROM mothbus_github_deliveries WHERE received_at < ?")
+        .bind(now - chrono::Duration::days(RETENTION_DAYS))
+        .execute(&state.mysql_pool)
+        .await
+        .context("failed to prune github deliveries")?;
+
+    Ok(())
+}
+
+fn stored_payload(payload: &[u8], signature_valid: bool) -> (String, bool) {
+    let max_length = if signature_valid {
+        MAX_PAYLOAD_LENGTH
+    } else {
+        MAX_UNVERIFIED_PAYLOAD_LENGTH
+    };
+
+    // Cut before converting, so an unverified payload is never copied in full
+    let payload_truncated = payload.len() > max_length;
+    let mut payload =
+        String::from_utf8_lossy(&payload[..payload.len().min(max_length)]).into_owned();
+
+    if payload_truncated {
+        // Whatever's left of a character split in two was replaced, and is dropped
+        let mut cut = payload.len().min(max_length);
+        while !payload.is_char_boundary(cut) {
+            cut -= 1;
+        }
+
+        payload.truncate(cut);
+    }
+
+    (payload, payload_truncated)
+}
+
+#[derive(Debug, Serialize)]
+pub struct StoredDelivery {
+    pub id: u64,
+    pub delivery_id: String,
+    pub event: String,
+    pub action: Option<String>,
+    pub repository: Option<String>,
+    pub signature_valid: bool,
+    pub outcome: String,
+    pub payload_truncated: bool,
+    pub received_at: chrono::NaiveDateTime,
+    pub replay_of: Option<u64>,
+}
+
+impl StoredDelivery {
+    fn from_row(row: &MySqlRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            delivery_id: row.try_get("delivery_id")?,
+            event: row.try_get("event")?,
+            action: row.try_get("action")?,
+            repository: row.try_get("repository")?,
+            signature_valid: row.try_get("signature_valid")?,
+            outcome: row.try_get("outcome")?,
+            payload_truncated: row.try_get("payload_truncated")?,
+            received_at: row.try_get("received_at")?,
+            replay_of: row.try_get("replay_of")?,
+        })
+    }
+}
+
+#[tracing::instrument(skip(mysql_pool))]
+pub async fn recent_deliveries(
+    mysql_pool: &sqlx::MySqlPool,
+    event: Option<&str>,
+) -> color_eyre::Result<Vec<StoredDelivery>> {
+    let rows = sqlx::query(
+        "SELECT id, delivery_id, event, action, repository, signature_valid, outcome, payload_truncated, received_at, replay_of
+        FROM mothbus_github_deliveries
+        WHERE ? IS NULL OR event = ?
+        ORDER BY id DESC
+        LIMIT 100",
+    )
+    .bind(event)
+    .bind(event)
+    .fetch_all(mysql_pool)
+    .await
+    .context("failed to fetch github deliveries")?;
+
+    rows.iter()
+        .map(|row| StoredDelivery::from_row(row).map_err(Into::into))
+        .collect()
+}
+
+/// Returns the delivery along with its raw payload.
+#[tracing::instrument(skip(mysql_pool))]
+pub async fn delivery(
+    mysql_pool: &sqlx::MySqlPool,
+    id: u64,
+) -> color_eyre::Result<Option<(StoredDelivery, String)>> {
+    let Some(row) = sqlx::query(
+        "SELECT id, delivery_id, event, action, repository, signature_valid, outcome, payload_truncated, received_at, replay_of, payload
+        FROM mothbus_github_deliveries
+        WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_optional(mysql_pool)
+    .await
+    .context("failed to fetch github delivery")?
+    else {
+        return Ok(None);
+    };
+
+    Ok(Some((
+        StoredDelivery::from_row(&row)?,
+        row.try_get("payload")?,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn deduplicator_claims_once() {
+        let deduplicator = DeliveryDeduplicator::empty();
+
+        assert!(
+            deduplicator
+                .claim("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+                .await
+        );
+        assert!(
+            !deduplicator
+                .claim("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+                .await
+        );
+        assert_eq!(deduplicator.duplicates(), 1);
+
+        deduplicator
+            .release("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+            .await;
+        assert!(
+            deduplicator
+                .claim("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+                .await
+        );
+
+        assert!(deduplicator.claim("").await);
+        assert!(deduplicator.claim("").await);
+    }
+
+    #[test]
+    fn unverified_payloads_are_cut_short() {
+        let payload = "🦋".repeat(MAX_UNVERIFIED_PAYLOAD_LENGTH);
+
+        let (stored, truncated) = stored_payload(payload.as_bytes(), true);
+        assert_eq!(stored, payload);
+        assert!(!truncated);
+
+        let (stored, truncated) = stored_payload(payload.as_bytes(), false);
+        assert!(truncated);
+        assert!(stored.len() <= MAX_UNVERIFIED_PAYLOAD_LENGTH);
+        assert!(payload.starts_with(&stored));
+
+        let (stored, truncated) = stored_payload(b"{}", false);
+        assert_eq!(stored, "{}");
+        assert!(!truncated);
+    }
+}
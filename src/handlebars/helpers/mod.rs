@@ -4,6 +4,9 @@ use serde::Deserialize;
 mod english_duration;
 pub use english_duration::EnglishDuration;
 
+mod urls;
+pub use urls::{RoundLogsUrl, StatbusTicketUrl};
+
 pub(crate) fn read_param<'de, T: Deserialize<'de>>(
     path_and_json: &'de PathAndJson,
 ) -> Result<T, RenderError> {
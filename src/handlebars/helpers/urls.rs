@@ -0,0 +1,51 @@
+use handlebars::{Handlebars, Helper, HelperDef, RenderContext, RenderError};
+use serde::Deserialize;
+
+use crate::urls::UrlTemplates;
+
+#[derive(Deserialize)]
+struct Round {
+    #[serde(alias = "id")]
+    round_id: u64,
+    server: String,
+    #[serde(alias = "initialize_datetime")]
+    datetime: chrono::NaiveDateTime,
+}
+
+pub struct RoundLogsUrl(pub UrlTemplates);
+
+impl HelperDef for RoundLogsUrl {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc handlebars::Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<handlebars::ScopedJson<'reg, 'rc>, RenderError> {
+        let round: Round = super::require_param(helper, 0, "round")?;
+
+        Ok(handlebars::ScopedJson::from(serde_json::Value::String(
+            self.0
+                .round_logs(&round.server, round.round_id, round.datetime),
+        )))
+    }
+}
+
+pub struct StatbusTicketUrl(pub UrlTemplates);
+
+impl HelperDef for StatbusTicketUrl {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc handlebars::Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<handlebars::ScopedJson<'reg, 'rc>, RenderError> {
+        let round_id: u64 = super::require_param(helper, 0, "round_id")?;
+        let ticket: u64 = super::require_param(helper, 1, "ticket")?;
+
+        Ok(handlebars::ScopedJson::from(serde_json::Value::String(
+            self.0.statbus_ticket(round_id, ticket),
+        )))
+    }
+}
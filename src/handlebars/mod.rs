@@ -1,7 +1,7 @@
 use color_eyre::eyre::Context;
 use handlebars::{Handlebars, Helper, HelperDef, RenderContext, RenderError};
 
-use crate::state::User;
+use crate::{state::User, Config};
 
 mod helpers;
 
@@ -58,13 +58,21 @@ impl HelperDef for UserReadsTickets {
     }
 }
 
-pub fn create_handlebars() -> color_eyre::Result<Handlebars<'static>> {
+pub fn create_handlebars(config: &Config) -> color_eyre::Result<Handlebars<'static>> {
     let mut handlebars = Handlebars::new();
     handlebars.set_dev_mode(true);
 
     handlebars.register_helper("english_duration", Box::new(helpers::EnglishDuration));
     handlebars.register_helper("mothbus_version", Box::new(MothbusVersion));
     handlebars.register_helper("remove_html_tags", Box::new(RemoveHtmlTags));
+    handlebars.register_helper(
+        "round_logs_url",
+        Box::new(helpers::RoundLogsUrl(config.urls.clone())),
+    );
+    handlebars.register_helper(
+        "statbus_ticket_url",
+        Box::new(helpers::StatbusTicketUrl(config.urls.clone())),
+    );
     handlebars.register_helper("user_reads_tickets", Box::new(UserReadsTickets));
 
     for template in std::fs::read_dir("dist")? {
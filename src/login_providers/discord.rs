@@ -0,0 +1,147 @@
+use axum::async_trait;
+use color_eyre::eyre::Context;
+use serde::Deserialize;
+
+use crate::{config::OAuth2Options, State};
+
+use super::LoginProvider;
+
+const AUTHORIZE_URL: &str = "https://discord.com/oauth2/authorize";
+const TOKEN_URL: &str = "https://discord.com/api/oauth2/token";
+const USER_URL: &str = "https://discord.com/api/users/@me";
+
+/// Discord accounts, matched to ckeys through the links players make in game.
+pub struct Discord {
+    pub options: OAuth2Options,
+}
+
+#[async_trait]
+impl LoginProvider for Discord {
+    fn name(&self) -> &'static str {
+        "discord"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "Discord"
+    }
+
+    fn authorize_url(&self, oauth_state: &str) -> String {
+        super::authorize_url(
+            self.options
+                .authorize_url
+                .as_deref()
+                .unwrap_or(AUTHORIZE_URL),
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.options.client_id),
+                ("redirect_uri", &self.options.redirect_uri),
+                ("scope", "identify"),
+                ("state", oauth_state),
+            ],
+        )
+    }
+
+    async fn ckey_for_code(&self, state: &State, code: &str) -> color_eyre::Result<Option<String>> {
+        #[derive(Deserialize)]
+        struct UserResponse {
+            id: String,
+        }
+
+        let client = super::http_client()?;
+        let access_token = super::exchange_code(
+            &client,
+            self.options.token_url.as_deref().unwrap_or(TOKEN_URL),
+            code,
+            &self.options,
+        )
+        .await?;
+        let user_response: UserResponse = super::user_info(
+            &client,
+            self.options.user_url.as_deref().unwrap_or(USER_URL),
+            &access_token,
+        )
+        .await?;
+
+        ckey_for_discord_id(state, parse_discord_id(&user_response.id)?).await
+    }
+}
+
+/// `discord_links.discord_id` is a BIGINT. Compared with a string, MySQL would compare both as
+/// doubles, which can't tell apart snowflakes that are close together.
+fn parse_discord_id(id: &str) -> color_eyre::Result<u64> {
+    id.parse()
+        .with_context(|| format!("discord gave an invalid user id: {id:?}"))
+}
+
+/// Only links that were verified in game count, otherwise anyone could claim any ckey.
+async fn ckey_for_discord_id(state: &State, discord_id: u64) -> color_eyre::Result<Option<String>> {
+    sqlx::query_scalar(&format!(
+        "SELECT ckey
+        FROM {}.discord_links
+        WHERE discord_id = ? AND valid = 1
+        ORDER BY timestamp DESC
+        LIMIT 1",
+        state.config.db_schema,
+    ))
+    .bind(discord_id)
+    .fetch_optional(&state.mysql_pool)
+    .await
+    .context("failed to fetch discord link")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::login_providers::fake::{FakeAccount, FakeOAuthServer};
+
+    #[test]
+    fn discord_ids_are_exact() {
+        // As doubles, these are the same number
+        let (id, neighbour) = ("1180000000000000001", "1180000000000000002");
+        assert_eq!(
+            id.parse::<f64>().unwrap(),
+            neighbour.parse::<f64>().unwrap()
+        );
+
+        assert_eq!(parse_discord_id(id).unwrap(), 1180000000000000001);
+        assert_eq!(parse_discord_id(neighbour).unwrap(), 1180000000000000002);
+
+        for invalid in ["", "-1", "1.18e18", "1180000000000000001 OR 1", "moth"] {
+            assert!(parse_discord_id(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_discord_id_is_rejected() {
+        let fake = FakeOAuthServer::start(FakeAccount::User(serde_json::json!({
+            "id": "1.18e18",
+        })))
+        .await;
+
+        let discord = Discord {
+            options: fake.options("http://mothbus.test/oauth/discord"),
+        };
+
+        let authorized = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .get(discord.authorize_url("state"))
+            .send()
+            .await
+            .unwrap();
+
+        let callback = authorized.headers()[http::header::LOCATION]
+            .to_str()
+            .unwrap();
+        let (_, query) = callback.split_once('?').unwrap();
+        let (_, code) = form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == "code")
+            .unwrap();
+
+        // Rejected before the database, which tests don't have
+        let state = State::for_tests(State::test_config(""));
+        let error = discord.ckey_for_code(&state, &code).await.unwrap_err();
+        assert!(format!("{error}").contains("invalid user id"), "{error:#}");
+    }
+}
@@ -0,0 +1,168 @@
+//! A stand-in OAuth server, so the login flow can be tested without the network.
+//! Everyone who logs in through it is the same account, given when it's started.
+
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{Form, Query},
+    response::{IntoResponse, Redirect},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use http::{header::AUTHORIZATION, HeaderMap, StatusCode};
+use serde::Deserialize;
+
+use crate::config::OAuth2Options;
+
+pub const CLIENT_ID: &str = "mothbus";
+pub const CLIENT_SECRET: &str = "hunter2";
+
+pub enum FakeAccount {
+    /// Logging in succeeds, and the user info endpoint returns this.
+    User(serde_json::Value),
+
+    /// The player declines to log in, and is sent back with an error.
+    Denied,
+}
+
+pub struct FakeOAuthServer {
+    url: String,
+}
+
+struct FakeState {
+    account: FakeAccount,
+    next_code: Mutex<u64>,
+    /// Codes that haven't been traded in yet. Like a real server, each works once.
+    codes: Mutex<HashSet<String>>,
+}
+
+impl FakeOAuthServer {
+    pub async fn start(account: FakeAccount) -> Self {
+        let fake_state = Arc::new(FakeState {
+            account,
+            next_code: Mutex::new(0),
+            codes: Mutex::new(HashSet::new()),
+        });
+
+        let app = Router::new()
+            .route("/authorize", get(authorize))
+            .route("/token", post(token))
+            .route("/user", get(user))
+            .layer(Extension(fake_state));
+
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let url = format!("http://{}", server.local_addr());
+
+        tokio::spawn(server);
+
+        Self { url }
+    }
+
+    pub fn options(&self, redirect_uri: &str) -> OAuth2Options {
+        OAuth2Options {
+            client_id: CLIENT_ID.to_owned(),
+            client_secret: CLIENT_SECRET.to_owned(),
+            redirect_uri: redirect_uri.to_owned(),
+            authorize_url: Some(format!("{}/authorize", self.url)),
+            token_url: Some(format!("{}/token", self.url)),
+            user_url: Some(format!("{}/user", self.url)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AuthorizeQuery {
+    client_id: String,
+    redirect_uri: String,
+    state: String,
+}
+
+// Skips the part where the player is asked, and answers straight away
+async fn authorize(
+    Extension(fake_state): Extension<Arc<FakeState>>,
+    Query(query): Query<AuthorizeQuery>,
+) -> impl IntoResponse {
+    if query.client_id != CLIENT_ID {
+        return (StatusCode::BAD_REQUEST, "unknown client").into_response();
+    }
+
+    let parameters = match fake_state.account {
+        FakeAccount::User(_) => {
+            let code = {
+                let mut next_code = fake_state.next_code.lock().unwrap();
+                *next_code += 1;
+                format!("code-{next_code}")
+            };
+
+            fake_state.codes.lock().unwrap().insert(code.clone());
+
+            vec![("code", code), ("state", query.state)]
+        }
+
+        FakeAccount::Denied => vec![
+            ("error", "access_denied".to_owned()),
+            ("errordesc", "The player declined".to_owned()),
+            ("state", query.state),
+        ],
+    };
+
+    Redirect::to(&format!(
+        "{}?{}",
+        query.redirect_uri,
+        form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(parameters)
+            .finish()
+    ))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct TokenForm {
+    code: String,
+    client_id: String,
+    client_secret: String,
+}
+
+async fn token(
+    Extension(fake_state): Extension<Arc<FakeState>>,
+    Form(form): Form<TokenForm>,
+) -> impl IntoResponse {
+    if form.client_id != CLIENT_ID || form.client_secret != CLIENT_SECRET {
+        return Json(serde_json::json!({
+            "error": "invalid_client",
+            "error_description": "Client authentication failed",
+        }));
+    }
+
+    if !fake_state.codes.lock().unwrap().remove(&form.code) {
+        return Json(serde_json::json!({
+            "error": "invalid_grant",
+            "error_description": "The code is invalid or has already been used",
+        }));
+    }
+
+    Json(serde_json::json!({
+        "access_token": format!("token-for-{}", form.code),
+        "token_type": "Bearer",
+    }))
+}
+
+async fn user(
+    Extension(fake_state): Extension<Arc<FakeState>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let authorized = headers
+        .get(AUTHORIZATION)
+        .and_then(|authorization| authorization.to_str().ok())
+        .is_some_and(|authorization| authorization.starts_with("Bearer token-for-"));
+
+    match &fake_state.account {
+        FakeAccount::User(user) if authorized => Json(user.clone()).into_response(),
+        _ => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
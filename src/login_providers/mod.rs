@@ -0,0 +1,150 @@
+//! The ways players can log in. Each provider is an OAuth2 server that can tell us the ckey
+//! of whoever logged in, and is configured alongside `[oauth2]`.
+
+use axum::async_trait;
+use color_eyre::eyre::Context;
+use serde::Deserialize;
+
+use crate::{config::Config, State};
+
+mod discord;
+mod tgforums;
+
+#[cfg(test)]
+pub mod fake;
+
+#[async_trait]
+pub trait LoginProvider: Send + Sync {
+    /// Used in `/login/<name>` and `/oauth/<name>`.
+    fn name(&self) -> &'static str;
+
+    /// Shown on the login page, as "login with <display name>".
+    fn display_name(&self) -> &'static str;
+
+    fn authorize_url(&self, oauth_state: &str) -> String;
+
+    /// Returns `None` when the account has no ckey linked.
+    async fn ckey_for_code(&self, state: &State, code: &str) -> color_eyre::Result<Option<String>>;
+}
+
+/// The first provider is the default, used by `/oauth` from before there were several.
+pub fn login_providers_from_config(config: &Config) -> Vec<Box<dyn LoginProvider>> {
+    let mut providers: Vec<Box<dyn LoginProvider>> = vec![Box::new(tgforums::TgForums {
+        options: config.oauth2.clone(),
+    })];
+
+    if let Some(options) = &config.discord_oauth2 {
+        providers.push(Box::new(discord::Discord {
+            options: options.clone(),
+        }));
+    }
+
+    providers
+}
+
+fn authorize_url(base: &str, parameters: &[(&str, &str)]) -> String {
+    format!(
+        "{base}?{}",
+        form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(parameters)
+            .finish()
+    )
+}
+
+fn http_client() -> color_eyre::Result<reqwest::Client> {
+    reqwest::ClientBuilder::new()
+        .user_agent("moth.fans")
+        .build()
+        .context("couldn't create http client")
+}
+
+/// Trades an authorization code for an access token, the same way for every provider.
+async fn exchange_code(
+    client: &reqwest::Client,
+    token_url: &str,
+    code: &str,
+    options: &crate::config::OAuth2Options,
+) -> color_eyre::Result<String> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum AuthorizationCodeResponse {
+        Success {
+            access_token: String,
+        },
+
+        Error {
+            error: String,
+            #[serde(default)]
+            error_description: String,
+        },
+    }
+
+    tracing::debug!("requesting access token for {code}");
+
+    let authorization_token_response_text = client
+        .post(token_url)
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("client_id", &options.client_id),
+            ("client_secret", &options.client_secret),
+            ("redirect_uri", &options.redirect_uri),
+        ])
+        .send()
+        .await
+        .context("error sending request to oauth server")?
+        .text()
+        .await
+        .context("error getting response text for authorization token")?;
+
+    match serde_json::from_str(&authorization_token_response_text) {
+        Ok(AuthorizationCodeResponse::Success { access_token }) => Ok(access_token),
+
+        Ok(AuthorizationCodeResponse::Error {
+            error,
+            error_description,
+        }) => {
+            tracing::error!("authorization response failure: {error_description} ({error})");
+
+            Err(color_eyre::eyre::eyre!(
+                "authorization response failure: {error_description} ({error})"
+            ))
+        }
+
+        Err(error) => {
+            tracing::error!(
+                "invalid authorization token response body: {authorization_token_response_text}"
+            );
+
+            Err(error).context("error parsing authorization token response")
+        }
+    }
+}
+
+/// Fetches whoever the access token belongs to.
+async fn user_info<T: serde::de::DeserializeOwned>(
+    client: &reqwest::Client,
+    user_url: &str,
+    access_token: &str,
+) -> color_eyre::Result<T> {
+    tracing::debug!("requesting user info for {access_token}");
+
+    let user_response_text = client
+        .get(user_url)
+        .header("Authorization", format!("Bearer {access_token}"))
+        .send()
+        .await
+        .context("error sending request to oauth server")?
+        .text()
+        .await
+        .context("error getting response text for user")?;
+
+    match serde_json::from_str(&user_response_text) {
+        Ok(user_response) => Ok(user_response),
+        Err(error) => {
+            tracing::error!("invalid user response body: {user_response_text}");
+
+            Err(error).context("error parsing user response")
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use axum::async_trait;
+use serde::Deserialize;
+
+use crate::{config::OAuth2Options, State};
+
+use super::LoginProvider;
+
+const AUTHORIZE_URL: &str = "https://tgstation13.org/phpBB/app.php/tgapi/oauth/auth";
+const TOKEN_URL: &str = "https://tgstation13.org/phpBB/app.php/tgapi/oauth/token";
+const USER_URL: &str = "https://tgstation13.org/phpBB/app.php/tgapi/user/me";
+
+/// The tgstation forums, which know the ckey of anyone who has linked their BYOND account.
+pub struct TgForums {
+    pub options: OAuth2Options,
+}
+
+#[async_trait]
+impl LoginProvider for TgForums {
+    fn name(&self) -> &'static str {
+        "tgforums"
+    }
+
+    fn display_name(&self) -> &'static str {
+        "your forum account"
+    }
+
+    fn authorize_url(&self, oauth_state: &str) -> String {
+        super::authorize_url(
+            self.options
+                .authorize_url
+                .as_deref()
+                .unwrap_or(AUTHORIZE_URL),
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.options.client_id),
+                ("redirect_uri", &self.options.redirect_uri),
+                ("state", oauth_state),
+            ],
+        )
+    }
+
+    async fn ckey_for_code(&self, _: &State, code: &str) -> color_eyre::Result<Option<String>> {
+        #[derive(Deserialize)]
+        struct UserResponse {
+            byond_ckey: Option<String>,
+        }
+
+        let client = super::http_client()?;
+        let access_token = super::exchange_code(
+            &client,
+            self.options.token_url.as_deref().unwrap_or(TOKEN_URL),
+            code,
+            &self.options,
+        )
+        .await?;
+        let user_response: UserResponse = super::user_info(
+            &client,
+            self.options.user_url.as_deref().unwrap_or(USER_URL),
+            &access_token,
+        )
+        .await?;
+
+        Ok(user_response.byond_ckey)
+    }
+}
@@ -1,12 +1,29 @@
+mod access_log;
+mod api_tokens;
 mod auth;
 mod block_templates;
+mod client_info;
 mod config;
+mod cookies;
+mod csrf;
+mod discord_queue;
+mod github_deliveries;
 mod handlebars;
 mod hide_debug;
+mod login_providers;
+mod pull_requests;
+mod rate_limit;
+mod relay;
+mod rights;
 mod routes;
+mod schema;
+mod security_headers;
 mod servers;
 mod session;
+mod session_store;
+mod signing_keys;
 mod state;
+mod urls;
 
 pub use config::Config;
 use http::StatusCode;
@@ -17,11 +34,15 @@ use std::{net::SocketAddr, sync::Arc};
 use axum::{
     extract::Extension,
     handler::Handler,
+    middleware,
     response::IntoResponse,
     routing::{get, get_service, post},
     Router,
 };
+use client_info::ClientInfo;
 use color_eyre::eyre::Context;
+use rate_limit::{Budget, RateLimitLayer};
+use security_headers::SecurityHeadersLayer;
 use tower_http::trace::TraceLayer;
 
 #[tokio::main]
@@ -29,6 +50,14 @@ async fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
     tracing_subscriber::fmt::init();
 
+    signing_keys::load().context("failed to load jwt signing keys")?;
+
+    if std::env::args().nth(1).as_deref() == Some("rotate-jwt-key") {
+        let kid = signing_keys::rotate().context("failed to rotate jwt signing key")?;
+        println!("now signing with {kid}, running servers will pick it up within an hour");
+        return Ok(());
+    }
+
     tracing::info!("starting mothbus");
 
     let state = Arc::new(State::new().await.context("failed to create state")?);
@@ -41,34 +70,109 @@ async fn main() -> color_eyre::Result<()> {
             .context("failed to get db version")?
     );
 
-    // Generating session token can panic, so just get it early
-    tracing::info!(
-        "session token length: {}",
-        session::get_secret_token().len()
-    );
+    tracing::info!("jwt signing keys: {}", signing_keys::key_count());
 
-    let address = state.config.address;
-    let port = state.config.port;
+    tokio::spawn(discord_queue::run_worker(Arc::clone(&state)));
+    tokio::spawn(signing_keys::run_rotation(
+        state.config.jwt_key_rotation_days,
+    ));
 
-    let app = Router::new()
-        .route("/", get(routes::index))
-        .route("/github-webhook", post(routes::github_webhook))
+    let address = SocketAddr::from((state.config.address, state.config.port));
+    let app = router(state);
+
+    tracing::debug!("listening on {}", address);
+
+    axum::Server::bind(&address)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
+
+    Ok(())
+}
+
+fn router(state: Arc<State>) -> Router {
+    let config = state.config.clone();
+
+    // Each player or address has one budget for each group of routes
+    let login_routes = Router::new()
         .route("/login", get(routes::login::index))
-        .route("/logout", get(routes::logout))
+        .route("/login/:provider", get(routes::login::login_with))
         .route("/mock-login/:ckey", get(routes::login::mock_login))
         .route("/oauth", get(routes::login::oauth))
-        .route("/@:ckey", get(routes::user::for_ckey))
-        .route("/recent-test-merges.json", get(routes::recent_test_merges))
-        .route("/round-info.json", get(routes::round_info))
-        .route("/rank-logs", get(routes::rank_logs))
+        .route("/oauth/:provider", get(routes::login::oauth_for))
+        .route_layer(RateLimitLayer::new(Budget::Login));
+
+    let poll_routes = Router::new()
         .route("/polls", get(routes::polls::index))
+        .route("/polls.json", get(routes::polls::index_json))
         .route("/polls/:poll", get(routes::polls::for_poll))
-        .route("/tickets", get(routes::tickets::index))
+        .route_layer(RateLimitLayer::new(Budget::Polls));
+
+    let ticket_routes = Router::new()
         .route("/tickets/@:ckey", get(routes::tickets::for_ckey))
         .route("/tickets/server/:server", get(routes::tickets::for_server))
         .route("/tickets/:round/:ticket", get(routes::tickets::for_ticket))
         .route("/tickets/:round", get(routes::tickets::for_round))
-        .nest("/evasion", ban_evasion_service())
+        .route_layer(RateLimitLayer::new(Budget::Tickets))
+        .route_layer(SecurityHeadersLayer::allowing_embeds());
+
+    Router::new()
+        .route("/", get(routes::index))
+        .route("/admin/access-log", get(routes::access_log::index))
+        .route(
+            "/admin/api-tokens",
+            get(routes::api_tokens::index).post(routes::api_tokens::create),
+        )
+        .route("/admin/api-tokens/:id", get(routes::api_tokens::for_token))
+        .route(
+            "/admin/api-tokens/:id/revoke",
+            post(routes::api_tokens::revoke),
+        )
+        .route(
+            "/admin/discord-deliveries",
+            get(routes::discord_deliveries::index),
+        )
+        .route(
+            "/admin/discord-deliveries/:id/replay",
+            post(routes::discord_deliveries::replay),
+        )
+        .route(
+            "/admin/github-deliveries",
+            get(routes::github_deliveries::index),
+        )
+        .route(
+            "/admin/github-deliveries/:id",
+            get(routes::github_deliveries::for_delivery),
+        )
+        .route(
+            "/admin/github-deliveries/:id/replay",
+            post(routes::github_deliveries::replay),
+        )
+        .route("/admin/sessions", get(routes::sessions::admin_index))
+        .route(
+            "/admin/sessions/revoke",
+            post(routes::sessions::admin_revoke_all),
+        )
+        .route("/github-webhook", post(routes::github_webhook))
+        .route("/hooks/:source", post(routes::hooks::relay))
+        .route("/logout", post(routes::logout))
+        .route("/@:ckey", get(routes::user::for_ckey))
+        .route("/recent-test-merges.json", get(routes::recent_test_merges))
+        .route("/round-info.json", get(routes::round_info))
+        .route(
+            "/rank-logs",
+            get(routes::rank_logs).layer(SecurityHeadersLayer::allowing_embeds()),
+        )
+        .route("/sessions", get(routes::sessions::index))
+        .route("/sessions/:id/revoke", post(routes::sessions::revoke))
+        .route("/tickets", get(routes::tickets::index))
+        .merge(login_routes)
+        .merge(poll_routes)
+        .merge(ticket_routes)
+        .nest(
+            "/evasion",
+            ban_evasion_service().layer(middleware::from_fn(access_log::record_evasion_views)),
+        )
         .nest(
             "/static",
             get_service(tower_http::services::ServeDir::new("dist"))
@@ -78,18 +182,19 @@ async fn main() -> color_eyre::Result<()> {
                 .handle_error(handle_static_error),
         )
         .fallback(routes::not_found.into_service())
+        .layer(middleware::from_fn(auth::api_token_layer))
+        .layer(SecurityHeadersLayer::new())
         .layer(Extension(state))
-        .layer(TraceLayer::new_for_http());
-
-    let address = SocketAddr::from((address, port));
-    tracing::debug!("listening on {}", address);
-
-    axum::Server::bind(&address)
-        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
-        .await
-        .unwrap();
-
-    Ok(())
+        .layer(TraceLayer::new_for_http().make_span_with(
+            move |request: &http::Request<axum::body::Body>| {
+                tracing::debug_span!(
+                    "request",
+                    method = %request.method(),
+                    uri = %request.uri(),
+                    client = %ClientInfo::from_request_ref(request, &config).ip,
+                )
+            },
+        ))
 }
 
 async fn handle_static_error(error: std::io::Error) -> impl IntoResponse {
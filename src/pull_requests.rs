@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use color_eyre::eyre::Context;
+use serde::Serialize;
+use sqlx::{MySql, QueryBuilder, Row};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PullRequest {
+    pub number: u64,
+    pub title: String,
+    pub author: String,
+    pub labels: Vec<String>,
+    pub state: String,
+}
+
+impl PullRequest {
+    pub fn from_webhook(pull_request: &serde_json::Value) -> Option<Self> {
+        Some(Self {
+            number: pull_request["number"].as_u64()?,
+            title: pull_request["title"].as_str()?.to_owned(),
+            author: pull_request["user"]["login"].as_str()?.to_owned(),
+            labels: pull_request["labels"]
+                .as_array()
+                .map(|labels| {
+                    labels
+                        .iter()
+                        .filter_map(|label| label["name"].as_str())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            state: if pull_request["merged"].as_bool() == Some(true) {
+                "merged".to_owned()
+            } else {
+                pull_request["state"].as_str()?.to_owned()
+            },
+        })
+    }
+}
+
+#[tracing::instrument(skip(mysql_pool))]
+pub async fn store_pull_request(
+    mysql_pool: &sqlx::MySqlPool,
+    repository: &str,
+    pull_request: &PullRequest,
+) -> color_eyre::Result<()> {
+    sqlx::query(
+        "INSERT INTO mothbus_pull_requests
+            (repository, number, title, author, labels, state, updated_at)
+        VALUES (?, ?, ?, ?, ?, ?, UTC_TIMESTAMP())
+        ON DUPLICATE KEY UPDATE
+            title = VALUES(title),
+            author = VALUES(author),
+            labels = VALUES(labels),
+            state = VALUES(state),
+            updated_at = VALUES(updated_at)",
+    )
+    .bind(repository)
+    .bind(pull_request.number)
+    .bind(&pull_request.title)
+    .bind(&pull_request.author)
+    .bind(serde_json::to_string(&pull_request.labels)?)
+    .bind(&pull_request.state)
+    .execute(mysql_pool)
+    .await
+    .context("failed to store pull request")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip(mysql_pool))]
+pub async fn pull_requests_by_number(
+    mysql_pool: &sqlx::MySqlPool,
+    repository: &str,
+    numbers: &[u64],
+) -> color_eyre::Result<HashMap<u64, PullRequest>> {
+    if numbers.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let mut query = QueryBuilder::<MySql>::new(
+        "SELECT number, title, author, labels, state FROM mothbus_pull_requests WHERE repository = ",
+    );
+
+    query.push_bind(repository).push(" AND number IN (");
+
+    let mut separated = query.separated(", ");
+    for number in numbers {
+        separated.push_bind(*number);
+    }
+    separated.push_unseparated(")");
+
+    let rows = query
+        .build()
+        .fetch_all(mysql_pool)
+        .await
+        .context("failed to fetch pull requests")?;
+
+    let mut pull_requests = HashMap::new();
+
+    for row in rows {
+        let number: u32 = row.try_get("number")?;
+        let labels: String = row.try_get("labels")?;
+
+        pull_requests.insert(
+            number.into(),
+            PullRequest {
+                number: number.into(),
+                title: row.try_get("title")?,
+                author: row.try_get("author")?,
+                labels: serde_json::from_str(&labels).unwrap_or_default(),
+                state: row.try_get("state")?,
+            },
+        );
+    }
+
+    Ok(pull_requests)
+}
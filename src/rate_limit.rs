@@ -0,0 +1,367 @@
+use std::{
+    future::Future,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::response::{IntoResponse, Response};
+use axum_extra::extract::cookie::Cookie;
+use http::{header::COOKIE, Request};
+use moka::future::Cache;
+use serde::Deserialize;
+use tower::{Layer, Service};
+
+use crate::{api_tokens::ApiToken, client_info::ClientInfo, cookies, Config, State};
+
+/// Routes that share a budget. Each player or address gets their own budget for each.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Budget {
+    /// The ticket listings, which run expensive queries.
+    Tickets,
+    /// Poll pages, which can rebuild the poll cache.
+    Polls,
+    /// Everything that starts or finishes a login.
+    Login,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub per_seconds: u64,
+}
+
+impl RateLimit {
+    fn period(self) -> Duration {
+        Duration::from_secs(self.per_seconds)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct RateLimits {
+    pub tickets: RateLimit,
+    pub polls: RateLimit,
+    pub login: RateLimit,
+}
+
+impl RateLimits {
+    fn get(&self, budget: Budget) -> RateLimit {
+        match budget {
+            Budget::Tickets => self.tickets,
+            Budget::Polls => self.polls,
+            Budget::Login => self.login,
+        }
+    }
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            tickets: RateLimit {
+                requests: 60,
+                per_seconds: 60,
+            },
+            polls: RateLimit {
+                requests: 60,
+                per_seconds: 60,
+            },
+            login: RateLimit {
+                requests: 20,
+                per_seconds: 60,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Window {
+    started_at: Instant,
+    requests: u32,
+}
+
+impl Window {
+    /// Counts a request, or returns how long until the window resets if it's full.
+    fn hit(&mut self, limit: RateLimit, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.started_at);
+
+        if elapsed >= limit.period() {
+            self.started_at = now;
+            self.requests = 0;
+        } else if self.requests >= limit.requests {
+            return Err(limit.period() - elapsed);
+        }
+
+        self.requests += 1;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    windows: Cache<(Budget, String), Arc<Mutex<Window>>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate_limits: &RateLimits) -> Self {
+        let longest_period = [Budget::Tickets, Budget::Polls, Budget::Login]
+            .into_iter()
+            .map(|budget| rate_limits.get(budget).period())
+            .max()
+            .unwrap_or_default();
+
+        Self {
+            windows: Cache::builder()
+                .max_capacity(100_000)
+                .time_to_idle(longest_period.max(Duration::from_secs(1)))
+                .build(),
+        }
+    }
+
+    /// Counts a request against the budget, or returns how long until another is allowed.
+    async fn check(
+        &self,
+        budget: Budget,
+        limit: RateLimit,
+        requester: String,
+    ) -> Result<(), Duration> {
+        let now = Instant::now();
+
+        let window = self
+            .windows
+            .get_with((budget, requester), async move {
+                Arc::new(Mutex::new(Window {
+                    started_at: now,
+                    requests: 0,
+                }))
+            })
+            .await;
+
+        let result = window
+            .lock()
+            .expect("rate limit lock poisoned")
+            .hit(limit, now);
+        result
+    }
+}
+
+/// Limits how often each player, token, or address can make requests to the routes it's on.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimitLayer {
+    budget: Budget,
+}
+
+impl RateLimitLayer {
+    pub fn new(budget: Budget) -> Self {
+        Self { budget }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            budget: self.budget,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    budget: Budget,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for RateLimitService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send,
+    ReqBody: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        // The service that was polled ready has to be the one that's called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let budget = self.budget;
+
+        Box::pin(async move {
+            let state = Arc::clone(
+                request
+                    .extensions()
+                    .get::<Arc<State>>()
+                    .expect("can't get state"),
+            );
+
+            let requester = Requester::from_request(&request, &state.config)
+                .key(&state)
+                .await;
+            let limit = state.config.rate_limits.get(budget);
+
+            if let Err(retry_after) = state.rate_limiter.check(budget, limit, requester).await {
+                return Ok(
+                    crate::routes::errors::make_too_many_requests(state, retry_after)
+                        .await
+                        .into_response(),
+                );
+            }
+
+            inner.call(request).await
+        })
+    }
+}
+
+/// Who a request is counted against. Logged in players and tokens are limited on their own,
+/// wherever they come from, and everyone else is limited by address.
+enum Requester {
+    ApiToken(u64),
+    Session {
+        session_jwt: String,
+        address: IpAddr,
+    },
+    Address(IpAddr),
+}
+
+impl Requester {
+    fn from_request<B>(request: &Request<B>, config: &Config) -> Self {
+        if let Some(token) = request.extensions().get::<ApiToken>() {
+            return Self::ApiToken(token.id);
+        }
+
+        let address = ClientInfo::from_request_ref(request, config).ip;
+
+        let session_jwt = request
+            .headers()
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|cookie| Cookie::parse_encoded(cookie.trim()).ok())
+            .find(|cookie| cookie.name() == cookies::SESSION);
+
+        match session_jwt {
+            Some(session_jwt) => Self::Session {
+                session_jwt: session_jwt.value().to_owned(),
+                address,
+            },
+
+            None => Self::Address(address),
+        }
+    }
+
+    async fn key(self, state: &Arc<State>) -> String {
+        match self {
+            Self::ApiToken(id) => format!("token:{id}"),
+
+            Self::Session {
+                session_jwt,
+                address,
+            } => match Arc::clone(state).session(&session_jwt).await {
+                Ok(Some(session)) => format!("ckey:{}", session.ckey),
+                Ok(None) => format!("ip:{address}"),
+                Err(error) => {
+                    tracing::error!("error getting session for rate limiting: {error:#?}");
+                    format!("ip:{address}")
+                }
+            },
+
+            Self::Address(address) => format!("ip:{address}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{body::Body, extract::ConnectInfo, routing::get, Extension, Router};
+    use http::{header::RETRY_AFTER, StatusCode};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[test]
+    fn window_resets_after_period() {
+        let limit = RateLimit {
+            requests: 2,
+            per_seconds: 10,
+        };
+
+        let start = Instant::now();
+        let mut window = Window {
+            started_at: start,
+            requests: 0,
+        };
+
+        assert!(window.hit(limit, start).is_ok());
+        assert!(window.hit(limit, start + Duration::from_secs(1)).is_ok());
+        assert_eq!(
+            window.hit(limit, start + Duration::from_secs(4)),
+            Err(Duration::from_secs(6))
+        );
+
+        assert!(window.hit(limit, start + Duration::from_secs(10)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn too_many_requests() {
+        let mut config = State::test_config(indoc::indoc! {r#"
+
+            [rate_limits]
+            login = { requests = 2, per_seconds = 60 }
+        "#});
+
+        let proxy = "127.0.0.1".parse().unwrap();
+        config.trusted_proxies = vec![proxy];
+
+        let app = Router::new()
+            .route(
+                "/login",
+                get(|| async { "logging in" }).layer(RateLimitLayer::new(Budget::Login)),
+            )
+            .layer(Extension(Arc::new(State::for_tests(config))));
+
+        let request = |forwarded_for: &'static str| {
+            let mut request = Request::get("/login")
+                .header("x-forwarded-for", forwarded_for)
+                .body(Body::empty())
+                .unwrap();
+
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::new(proxy, 1234)));
+
+            app.clone().oneshot(request)
+        };
+
+        for _ in 0..2 {
+            assert_eq!(
+                request("203.0.113.7").await.unwrap().status(),
+                StatusCode::OK
+            );
+        }
+
+        let response = request("203.0.113.7").await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "60");
+
+        // Someone else behind the same proxy has their own budget
+        assert_eq!(
+            request("203.0.113.8").await.unwrap().status(),
+            StatusCode::OK
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use serde::Deserialize;
+
+use super::{RelayRequest, RelaySource, Verification};
+use crate::urls::UrlTemplates;
+
+const COLOR_SUCCESS: u32 = 0x2cbe4e;
+const COLOR_FAILURE: u32 = 0xcb2431;
+const COLOR_OTHER: u32 = 0x959da5;
+
+/// Results of CI runs, posted by a step at the end of the workflow.
+pub struct CiSource {
+    pub verification: Verification,
+}
+
+#[derive(Deserialize)]
+struct CiPayload {
+    repository: String,
+    workflow: String,
+    status: String,
+    branch: Option<String>,
+    commit: Option<String>,
+    author: Option<String>,
+    url: Option<String>,
+}
+
+impl RelaySource for CiSource {
+    fn verify(&self, request: &RelayRequest) -> Result<(), String> {
+        self.verification.verify(request)
+    }
+
+    fn embed(
+        &self,
+        _: &RelayRequest,
+        payload: &serde_json::Value,
+        _: &UrlTemplates,
+    ) -> Option<serde_json::Value> {
+        let payload = CiPayload::deserialize(payload).ok()?;
+
+        let color = match payload.status.as_str() {
+            "success" => COLOR_SUCCESS,
+            "failure" => COLOR_FAILURE,
+            _ => COLOR_OTHER,
+        };
+
+        let mut description = Vec::new();
+
+        if let Some(branch) = &payload.branch {
+            description.push(format!("Branch: `{branch}`"));
+        }
+
+        if let Some(commit) = &payload.commit {
+            description.push(format!("Commit: `{}`", commit.get(..7).unwrap_or(commit)));
+        }
+
+        Some(serde_json::json!({
+            "title": format!("[{}] {}: {}", payload.repository, payload.workflow, payload.status),
+            "description": description.join("\n"),
+            "url": payload.url,
+            "color": color,
+            "author": payload.author.map(|author| serde_json::json!({ "name": author })),
+        }))
+    }
+}
@@ -0,0 +1,220 @@
+//! Relays webhooks from other services to Discord. Each source verifies its own requests
+//! and maps its own payloads to embeds, and is configured under `[hooks.<name>]`.
+
+use std::collections::HashMap;
+
+use hmac::{Hmac, Mac};
+use http::HeaderMap;
+use serde::Deserialize;
+
+use crate::{
+    client_info::ClientInfo, config::HookOptions, csrf::constant_time_eq, urls::UrlTemplates,
+};
+
+mod ci;
+mod round_end;
+
+pub struct RelayRequest<'a> {
+    pub headers: &'a HeaderMap,
+    pub body: &'a [u8],
+    pub client_info: ClientInfo,
+}
+
+impl RelayRequest<'_> {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+}
+
+pub trait RelaySource: Send + Sync {
+    fn verify(&self, request: &RelayRequest) -> Result<(), String>;
+
+    /// Returns `None` when the payload isn't something that should be sent to Discord.
+    fn embed(
+        &self,
+        request: &RelayRequest,
+        payload: &serde_json::Value,
+        urls: &UrlTemplates,
+    ) -> Option<serde_json::Value>;
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Verification {
+    /// Signed the same way as GitHub, with an optional `sha256=` prefix.
+    HmacSha256 {
+        secret: String,
+        #[serde(default = "default_signature_header")]
+        header: String,
+    },
+
+    SharedToken {
+        token: String,
+        #[serde(default = "default_token_header")]
+        header: String,
+    },
+
+    /// Only accepts requests from localhost.
+    None,
+}
+
+fn default_signature_header() -> String {
+    "X-Hub-Signature-256".to_owned()
+}
+
+fn default_token_header() -> String {
+    "X-Hook-Token".to_owned()
+}
+
+impl Verification {
+    pub fn verify(&self, request: &RelayRequest) -> Result<(), String> {
+        match self {
+            Verification::HmacSha256 { secret, header } => {
+                let signature = request
+                    .header(header)
+                    .ok_or_else(|| "missing signature".to_owned())?;
+
+                verify_signature(secret, request.body, signature).map_err(|calculated| {
+                    tracing::debug!("invalid signature: {signature}, expected {calculated}");
+                    "invalid signature".to_owned()
+                })
+            }
+
+            Verification::SharedToken { token, header } => match request.header(header) {
+                Some(given) if constant_time_eq(given.as_bytes(), token.as_bytes()) => Ok(()),
+                Some(_) => Err("invalid token".to_owned()),
+                None => Err("missing token".to_owned()),
+            },
+
+            Verification::None => {
+                if request.client_info.is_local() {
+                    Ok(())
+                } else {
+                    Err("unverified hooks are only allowed on localhost".to_owned())
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn bytes_to_hex_display(input: &[u8]) -> String {
+    let mut bytes = String::new();
+
+    for byte in input {
+        bytes.push_str(&format!("{:02x}", byte));
+    }
+
+    bytes
+}
+
+pub(crate) fn verify_signature(secret: &str, body: &[u8], signature: &str) -> Result<(), String> {
+    let mut hmac: Hmac<sha2::Sha256> =
+        Hmac::new_from_slice(secret.as_bytes()).expect("failed to create hmac");
+    hmac.update(body);
+
+    let signature = signature.replace("sha256=", "");
+    let finalized = bytes_to_hex_display(&hmac.finalize().into_bytes()[..]);
+
+    if constant_time_eq(finalized.as_bytes(), signature.as_bytes()) {
+        Ok(())
+    } else {
+        Err(finalized)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookKind {
+    RoundEnd,
+    Ci,
+}
+
+pub struct Hook {
+    pub source: Box<dyn RelaySource>,
+    pub discord_url: String,
+    pub mention_role: Option<String>,
+}
+
+pub fn hooks_from_config(hooks: &HashMap<String, HookOptions>) -> HashMap<String, Hook> {
+    hooks
+        .iter()
+        .map(|(name, options)| {
+            let verification = options.verification.clone();
+
+            let source: Box<dyn RelaySource> = match options.kind {
+                HookKind::RoundEnd => Box::new(round_end::RoundEndSource { verification }),
+                HookKind::Ci => Box::new(ci::CiSource { verification }),
+            };
+
+            (
+                name.clone(),
+                Hook {
+                    source,
+                    discord_url: options.discord_url.clone(),
+                    mention_role: options.mention_role.clone(),
+                },
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request<'a>(headers: &'a HeaderMap, body: &'a [u8], ip: [u8; 4]) -> RelayRequest<'a> {
+        RelayRequest {
+            headers,
+            body,
+            client_info: ClientInfo::resolve(ip.into(), headers, &[], Default::default()),
+        }
+    }
+
+    #[test]
+    fn verify_signature_simple() {
+        assert_eq!(
+            verify_signature(
+                "It's a Secret to Everybody",
+                b"Hello, World!",
+                "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17"
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn verify_shared_token() {
+        let verification = Verification::SharedToken {
+            token: "hunter2".to_owned(),
+            header: default_token_header(),
+        };
+
+        let mut headers = HeaderMap::new();
+        assert!(verification
+            .verify(&request(&headers, b"", [1, 2, 3, 4]))
+            .is_err());
+
+        headers.insert("X-Hook-Token", "hunter3".parse().unwrap());
+        assert!(verification
+            .verify(&request(&headers, b"", [1, 2, 3, 4]))
+            .is_err());
+
+        headers.insert("X-Hook-Token", "hunter2".parse().unwrap());
+        assert!(verification
+            .verify(&request(&headers, b"", [1, 2, 3, 4]))
+            .is_ok());
+    }
+
+    #[test]
+    fn verify_none_localhost_only() {
+        let headers = HeaderMap::new();
+
+        assert!(Verification::None
+            .verify(&request(&headers, b"", [127, 0, 0, 1]))
+            .is_ok());
+
+        assert!(Verification::None
+            .verify(&request(&headers, b"", [1, 2, 3, 4]))
+            .is_err());
+    }
+}
@@ -0,0 +1,129 @@
+use serde::Deserialize;
+
+use super::{RelayRequest, RelaySource, Verification};
+use crate::urls::UrlTemplates;
+
+const COLOR: u32 = 0x3b7ddd;
+
+/// Sent by game servers when a round ends.
+pub struct RoundEndSource {
+    pub verification: Verification,
+}
+
+#[derive(Deserialize)]
+struct RoundEndPayload {
+    round_id: u64,
+    server: String,
+    map: Option<String>,
+    mode: Option<String>,
+    /// In minutes
+    duration: Option<u64>,
+    /// Logs are filed under the day the round started, so there's no link without it.
+    start_datetime: Option<chrono::NaiveDateTime>,
+    end_state: Option<String>,
+}
+
+impl RelaySource for RoundEndSource {
+    fn verify(&self, request: &RelayRequest) -> Result<(), String> {
+        self.verification.verify(request)
+    }
+
+    fn embed(
+        &self,
+        _: &RelayRequest,
+        payload: &serde_json::Value,
+        urls: &UrlTemplates,
+    ) -> Option<serde_json::Value> {
+        let payload = RoundEndPayload::deserialize(payload).ok()?;
+
+        let mut fields = Vec::new();
+
+        for (name, value) in [
+            ("Map", payload.map),
+            ("Mode", payload.mode),
+            (
+                "Duration",
+                payload
+                    .duration
+                    .map(|duration| format!("{}h {:02}m", duration / 60, duration % 60)),
+            ),
+            ("End state", payload.end_state),
+        ] {
+            if let Some(value) = value {
+                fields.push(serde_json::json!({
+                    "name": name,
+                    "value": value,
+                    "inline": true,
+                }));
+            }
+        }
+
+        Some(serde_json::json!({
+            "title": format!("Round #{} ended on {}", payload.round_id, payload.server),
+            "url": payload.start_datetime.map(|start_datetime| {
+                urls.round_logs(&payload.server, payload.round_id, start_datetime)
+            }),
+            "color": COLOR,
+            "fields": fields,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderMap;
+
+    use super::*;
+    use crate::client_info::ClientInfo;
+
+    fn embed(payload: serde_json::Value) -> serde_json::Value {
+        let headers = HeaderMap::new();
+
+        RoundEndSource {
+            verification: Verification::None,
+        }
+        .embed(
+            &RelayRequest {
+                headers: &headers,
+                body: b"",
+                client_info: ClientInfo::resolve(
+                    [127, 0, 0, 1].into(),
+                    &headers,
+                    &[],
+                    Default::default(),
+                ),
+            },
+            &payload,
+            &UrlTemplates::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn logs_are_from_the_day_the_round_started() {
+        let embed = embed(serde_json::json!({
+            "round_id": 219951,
+            "server": "manuel",
+            "duration": 95,
+            "start_datetime": "2023-12-04T23:40:00",
+            "end_datetime": "2023-12-05T01:15:00",
+        }));
+
+        assert_eq!(
+            embed["url"],
+            "https://tgstation13.org/parsed-logs/manuel/data/logs/2023/12/04/round-219951/"
+        );
+        assert_eq!(embed["fields"][0]["value"], "1h 35m");
+    }
+
+    #[test]
+    fn no_logs_without_a_start() {
+        let embed = embed(serde_json::json!({
+            "round_id": 219951,
+            "server": "manuel",
+            "end_datetime": "2023-12-05T01:15:00",
+        }));
+
+        assert!(embed["url"].is_null());
+    }
+}
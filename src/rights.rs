@@ -0,0 +1,153 @@
+use serde::{Deserialize, Serialize};
+
+/// Admin rights, matching the game's `R_*` defines.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Right {
+    #[serde(rename = "R_BUILD")]
+    Build = 1 << 0,
+    #[serde(rename = "R_ADMIN")]
+    Admin = 1 << 1,
+    #[serde(rename = "R_BAN")]
+    Ban = 1 << 2,
+    #[serde(rename = "R_FUN")]
+    Fun = 1 << 3,
+    #[serde(rename = "R_SERVER")]
+    Server = 1 << 4,
+    #[serde(rename = "R_DEBUG")]
+    Debug = 1 << 5,
+    #[serde(rename = "R_POSSESS")]
+    Possess = 1 << 6,
+    #[serde(rename = "R_PERMISSIONS")]
+    Permissions = 1 << 7,
+    #[serde(rename = "R_STEALTH")]
+    Stealth = 1 << 8,
+    #[serde(rename = "R_POLL")]
+    Poll = 1 << 9,
+    #[serde(rename = "R_VAREDIT")]
+    Varedit = 1 << 10,
+    #[serde(rename = "R_SOUND")]
+    Sound = 1 << 11,
+    #[serde(rename = "R_SPAWN")]
+    Spawn = 1 << 12,
+    #[serde(rename = "R_AUTOADMIN")]
+    Autoadmin = 1 << 13,
+    #[serde(rename = "R_DBRANKS")]
+    Dbranks = 1 << 14,
+    #[serde(rename = "R_SENSITIVE")]
+    Sensitive = 1 << 15,
+}
+
+impl Right {
+    pub const ALL: [Right; 16] = [
+        Right::Build,
+        Right::Admin,
+        Right::Ban,
+        Right::Fun,
+        Right::Server,
+        Right::Debug,
+        Right::Possess,
+        Right::Permissions,
+        Right::Stealth,
+        Right::Poll,
+        Right::Varedit,
+        Right::Sound,
+        Right::Spawn,
+        Right::Autoadmin,
+        Right::Dbranks,
+        Right::Sensitive,
+    ];
+
+    pub fn bit(self) -> u32 {
+        self as u32
+    }
+}
+
+/// A set of rights, as stored in the `flags` columns of `admin_ranks`.
+/// Serialized as a list of `R_*` names.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(from = "Vec<Right>", into = "Vec<Right>")]
+pub struct Rights(u32);
+
+impl Rights {
+    /// Bits that don't correspond to a known right are dropped.
+    pub fn from_bits(bits: u32) -> Self {
+        Self(bits & Self::from(Right::ALL.to_vec()).0)
+    }
+
+    pub fn contains(self, right: Right) -> bool {
+        self.0 & right.bit() != 0
+    }
+
+    pub fn contains_all(self, rights: &[Right]) -> bool {
+        rights.iter().all(|&right| self.contains(right))
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Right> {
+        Right::ALL
+            .into_iter()
+            .filter(move |&right| self.contains(right))
+    }
+}
+
+impl From<Vec<Right>> for Rights {
+    fn from(rights: Vec<Right>) -> Self {
+        Self(rights.into_iter().fold(0, |bits, right| bits | right.bit()))
+    }
+}
+
+impl From<Rights> for Vec<Right> {
+    fn from(rights: Rights) -> Self {
+        rights.iter().collect()
+    }
+}
+
+/// The rights needed for each thing mothbus lets admins do. Every listed right is required.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Permissions {
+    pub read_tickets: Vec<Right>,
+    pub read_admin_only_polls: Vec<Right>,
+    pub read_text_ckeys: Vec<Right>,
+    pub manage_webhooks: Vec<Right>,
+    pub manage_sessions: Vec<Right>,
+    pub manage_api_tokens: Vec<Right>,
+    pub read_access_log: Vec<Right>,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self {
+            read_tickets: vec![Right::Admin],
+            read_admin_only_polls: vec![Right::Admin],
+            read_text_ckeys: vec![Right::Admin],
+            manage_webhooks: vec![Right::Admin],
+            manage_sessions: vec![Right::Admin],
+            manage_api_tokens: vec![Right::Admin],
+            // Only headmins, since it shows what other admins have been looking at
+            read_access_log: vec![Right::Permissions],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rights_from_flags() {
+        let rights = Rights::from_bits((1 << 1) | (1 << 2) | (1 << 20));
+
+        assert_eq!(
+            rights.iter().collect::<Vec<_>>(),
+            [Right::Admin, Right::Ban]
+        );
+        assert!(rights.contains_all(&[Right::Admin, Right::Ban]));
+        assert!(!rights.contains_all(&[Right::Admin, Right::Fun]));
+        assert!(rights.contains_all(&[]));
+
+        assert_eq!(
+            serde_json::to_value(rights).unwrap(),
+            serde_json::json!(["R_ADMIN", "R_BAN"])
+        );
+    }
+}
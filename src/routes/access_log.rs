@@ -0,0 +1,214 @@
+use std::sync::Arc;
+
+use axum::{extract::Query, response::IntoResponse, Extension};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    access_log::{AccessKind, AccessLogEntry, AccessLogFilter, ENTRIES_PER_PAGE},
+    auth::AuthenticatedUser,
+    State,
+};
+
+use super::{
+    errors::{make_bad_request, make_forbidden, make_internal_server_error},
+    TemplateBase,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct AccessLogParams {
+    viewer: Option<String>,
+    kind: Option<String>,
+    target: Option<String>,
+    page: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct AccessLogTemplate {
+    base: TemplateBase,
+    entries: Vec<AccessLogEntry>,
+    kinds: Vec<AccessKindOption>,
+    viewer: Option<String>,
+    target: Option<String>,
+    page: u32,
+    next_page_query: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AccessKindOption {
+    name: &'static str,
+    selected: bool,
+}
+
+#[tracing::instrument]
+pub async fn index(
+    Query(params): Query<AccessLogParams>,
+    Extension(state): Extension<Arc<State>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+) -> impl IntoResponse {
+    if !user.can_read_access_log() {
+        return make_forbidden(state, "You do not have permission to read the access log.")
+            .await
+            .into_response();
+    }
+
+    let non_empty = |field: Option<String>| field.filter(|value| !value.trim().is_empty());
+
+    let viewer = non_empty(params.viewer);
+    let target = non_empty(params.target);
+    let kind = match non_empty(params.kind) {
+        Some(name) => match AccessKind::from_name(&name) {
+            Some(kind) => Some(kind),
+            None => {
+                return make_bad_request(state, &format!("{name} is not a kind of access"))
+                    .await
+                    .into_response();
+            }
+        },
+
+        None => None,
+    };
+    let page = params.page.unwrap_or(1).max(1);
+
+    let entries = match crate::access_log::entries(
+        &state.mysql_pool,
+        &AccessLogFilter {
+            viewer: viewer.as_deref(),
+            kind,
+            target: target.as_deref(),
+        },
+        page,
+    )
+    .await
+    {
+        Ok(entries) => entries,
+        Err(error) => {
+            return make_internal_server_error(state, error)
+                .await
+                .into_response();
+        }
+    };
+
+    // A full page means there might be more, unless this was already the last page there can be
+    let next_page = page
+        .checked_add(1)
+        .filter(|_| entries.len() == ENTRIES_PER_PAGE as usize);
+
+    let next_page_query = next_page.map(|next_page| {
+        let mut query = form_urlencoded::Serializer::new(String::new());
+
+        if let Some(viewer) = &viewer {
+            query.append_pair("viewer", viewer);
+        }
+
+        if let Some(kind) = kind {
+            query.append_pair("kind", kind.name());
+        }
+
+        if let Some(target) = &target {
+            query.append_pair("target", target);
+        }
+
+        query.append_pair("page", &next_page.to_string()).finish()
+    });
+
+    state.render_template(
+        "access_log",
+        AccessLogTemplate {
+            base: TemplateBase {
+                title: "access log".into(),
+                user: Some(user),
+            },
+
+            kinds: AccessKind::ALL
+                .into_iter()
+                .map(|option| AccessKindOption {
+                    name: option.name(),
+                    selected: Some(option) == kind,
+                })
+                .collect(),
+
+            entries,
+            viewer,
+            target,
+            page,
+            next_page_query,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{
+        body::{Body, HttpBody},
+        extract::ConnectInfo,
+        response::Response,
+    };
+    use http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::rights::Right;
+
+    fn test_state() -> Arc<State> {
+        crate::signing_keys::load_for_tests();
+
+        Arc::new(State::for_tests(State::test_config("")))
+    }
+
+    async fn get(state: &Arc<State>, uri: &str, session_jwt: &str) -> Response {
+        let mut request = Request::get(uri)
+            .header(http::header::COOKIE, format!("session_jwt={session_jwt}"))
+            .body(Body::empty())
+            .unwrap();
+
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 1234))));
+
+        crate::router(Arc::clone(state))
+            .oneshot(request)
+            .await
+            .unwrap()
+    }
+
+    async fn body_text(response: Response) -> String {
+        let mut body = response.into_body();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn only_for_those_who_can_read_it() {
+        let state = test_state();
+
+        for rights in [None, Some([Right::Admin, Right::Ban].as_slice())] {
+            let session_jwt = Arc::clone(&state)
+                .log_in_for_tests("mothblocks", rights)
+                .await;
+
+            let response = get(&state, "/admin/access-log", &session_jwt).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{rights:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_kind() {
+        let state = test_state();
+        let session_jwt = Arc::clone(&state)
+            .log_in_for_tests("mothblocks", Some(&[Right::Permissions]))
+            .await;
+
+        let response = get(&state, "/admin/access-log?kind=tickets", &session_jwt).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body_text(response).await,
+            "400: tickets is not a kind of access"
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::Path,
+    response::{IntoResponse, Redirect},
+    Extension,
+};
+use axum_extra::extract::cookie::CookieJar;
+use serde::Serialize;
+
+use crate::{
+    api_tokens::{ApiTokenRequest, NewApiToken, Scope, StoredApiToken},
+    auth::AuthenticatedUser,
+    client_info::ClientInfo,
+    cookies,
+    csrf::{CsrfForm, CsrfProtected},
+    state::User,
+    State,
+};
+
+use super::{
+    errors::{make_forbidden, make_internal_server_error, make_not_found},
+    TemplateBase,
+};
+
+const FORBIDDEN: &str = "You do not have permission to manage API tokens.";
+
+/// Ten years, which is as good as never, while keeping far away from what dates can hold.
+const MAX_EXPIRY_DAYS: u32 = 3650;
+
+#[derive(Serialize)]
+struct ApiTokensTemplate {
+    base: TemplateBase,
+    tokens: Vec<StoredApiToken>,
+    grantable_scopes: Vec<Scope>,
+    created_token: Option<String>,
+    error: Option<String>,
+}
+
+#[tracing::instrument(skip(cookie_jar))]
+pub async fn index(
+    Extension(state): Extension<Arc<State>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    cookie_jar: CookieJar,
+) -> impl IntoResponse {
+    if !user.can_manage_api_tokens() {
+        return make_forbidden(state, FORBIDDEN).await.into_response();
+    }
+
+    // Left by `create`, and shown only this once
+    let Some(created_token) = cookie_jar
+        .get(cookies::CREATED_API_TOKEN)
+        .map(|cookie| cookie.value().to_owned())
+    else {
+        return render_index(state, user, None, None).await.into_response();
+    };
+
+    (
+        cookies::remove_created_api_token(cookie_jar),
+        render_index(state, user, Some(created_token), None).await,
+    )
+        .into_response()
+}
+
+// Scopes are checkboxes sharing a name, which only a list of pairs can hold
+#[tracing::instrument(skip(cookie_jar, form))]
+pub async fn create(
+    Extension(state): Extension<Arc<State>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    client_info: ClientInfo,
+    cookie_jar: CookieJar,
+    CsrfForm(form): CsrfForm<Vec<(String, String)>>,
+) -> impl IntoResponse {
+    if !user.can_manage_api_tokens() {
+        return make_forbidden(state, FORBIDDEN).await.into_response();
+    }
+
+    let field = |name: &str| {
+        form.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.trim())
+            .filter(|value| !value.is_empty())
+    };
+
+    let Some(label) = field("label") else {
+        return render_index(state, user, None, Some("tokens need a label".to_owned()))
+            .await
+            .into_response();
+    };
+
+    let mut scopes = Vec::new();
+    for (_, name) in form.iter().filter(|(key, _)| key == "scope") {
+        match Scope::from_name(name) {
+            Some(scope) if user.can_grant(scope) => scopes.push(scope),
+            _ => {
+                return render_index(state, user, None, Some(format!("you can't grant {name}")))
+                    .await
+                    .into_response();
+            }
+        }
+    }
+
+    let expires_at = match field("expires_in_days") {
+        Some(days) => match expiry_after_days(days, chrono::Utc::now().naive_utc()) {
+            Some(expires_at) => Some(expires_at),
+            None => {
+                return render_index(
+                    state,
+                    user,
+                    None,
+                    Some(format!(
+                        "expiry must be between 1 and {MAX_EXPIRY_DAYS} days"
+                    )),
+                )
+                .await
+                .into_response();
+            }
+        },
+
+        None => None,
+    };
+
+    let created_token = match crate::api_tokens::create(
+        &state.mysql_pool,
+        &NewApiToken {
+            label,
+            scopes: &scopes,
+            created_by: &user.ckey,
+            expires_at,
+        },
+    )
+    .await
+    {
+        Ok(created_token) => created_token,
+        Err(error) => {
+            return make_internal_server_error(state, error)
+                .await
+                .into_response();
+        }
+    };
+
+    tracing::info!("{} created api token {label} ({scopes:?})", user.ckey);
+
+    (
+        cookies::add_created_api_token(cookie_jar, created_token, client_info),
+        Redirect::to("/admin/api-tokens"),
+    )
+        .into_response()
+}
+
+fn expiry_after_days(days: &str, now: chrono::NaiveDateTime) -> Option<chrono::NaiveDateTime> {
+    let days = days
+        .parse::<u32>()
+        .ok()
+        // A token expiring today would already be expired
+        .filter(|days| (1..=MAX_EXPIRY_DAYS).contains(days))?;
+    now.checked_add_signed(chrono::Duration::days(days.into()))
+}
+
+async fn render_index(
+    state: Arc<State>,
+    user: User,
+    created_token: Option<String>,
+    error: Option<String>,
+) -> impl IntoResponse {
+    let tokens = match crate::api_tokens::tokens(&state.mysql_pool).await {
+        Ok(tokens) => tokens,
+        Err(error) => {
+            return make_internal_server_error(state, error)
+                .await
+                .into_response();
+        }
+    };
+
+    state.render_template(
+        "api_tokens",
+        ApiTokensTemplate {
+            grantable_scopes: Scope::ALL
+                .into_iter()
+                .filter(|&scope| user.can_grant(scope))
+                .collect(),
+
+            base: TemplateBase {
+                title: "api tokens".into(),
+                user: Some(user),
+            },
+
+            tokens,
+            created_token,
+            error,
+        },
+    )
+}
+
+#[derive(Serialize)]
+struct ApiTokenTemplate {
+    base: TemplateBase,
+    token: StoredApiToken,
+    requests: Vec<ApiTokenRequest>,
+}
+
+#[tracing::instrument]
+pub async fn for_token(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<State>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+) -> impl IntoResponse {
+    if !user.can_manage_api_tokens() {
+        return make_forbidden(state, FORBIDDEN).await.into_response();
+    }
+
+    let (token, requests) = match crate::api_tokens::token(&state.mysql_pool, id).await {
+        Ok(Some(token)) => token,
+
+        Ok(None) => {
+            return make_not_found(state, "api token not found")
+                .await
+                .into_response();
+        }
+
+        Err(error) => {
+            return make_internal_server_error(state, error)
+                .await
+                .into_response();
+        }
+    };
+
+    state.render_template(
+        "api_token",
+        ApiTokenTemplate {
+            base: TemplateBase {
+                title: format!("api token {}", token.label).into(),
+                user: Some(user),
+            },
+
+            token,
+            requests,
+        },
+    )
+}
+
+#[tracing::instrument]
+pub async fn revoke(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<State>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    _csrf: CsrfProtected,
+) -> impl IntoResponse {
+    if !user.can_manage_api_tokens() {
+        return make_forbidden(state, FORBIDDEN).await.into_response();
+    }
+
+    match crate::api_tokens::revoke(&state.mysql_pool, id).await {
+        Ok(true) => {
+            tracing::info!("{} revoked api token {id}", user.ckey);
+            Redirect::to("/admin/api-tokens").into_response()
+        }
+
+        Ok(false) => make_not_found(state, "api token not found")
+            .await
+            .into_response(),
+
+        Err(error) => make_internal_server_error(state, error)
+            .await
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiry_is_bounded() {
+        let now = chrono::NaiveDate::from_ymd(2023, 1, 1).and_hms(0, 0, 0);
+
+        assert_eq!(
+            expiry_after_days("30", now),
+            Some(chrono::NaiveDate::from_ymd(2023, 1, 31).and_hms(0, 0, 0))
+        );
+        assert!(expiry_after_days("1", now).is_some());
+        assert!(expiry_after_days(&MAX_EXPIRY_DAYS.to_string(), now).is_some());
+
+        for days in ["0", "3651", "4000000000", "-1", "soon"] {
+            assert_eq!(expiry_after_days(days, now), None, "{days}");
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::Path,
+    response::{IntoResponse, Redirect},
+    Extension,
+};
+use serde::Serialize;
+
+use crate::{auth::AuthenticatedUser, csrf::CsrfProtected, discord_queue::FailedDelivery, State};
+
+use super::{
+    errors::{make_forbidden, make_internal_server_error, make_not_found},
+    TemplateBase,
+};
+
+const FORBIDDEN: &str = "You do not have permission to manage Discord deliveries.";
+
+#[derive(Serialize)]
+struct DiscordDeliveriesTemplate {
+    base: TemplateBase,
+    deliveries: Vec<FailedDelivery>,
+}
+
+#[tracing::instrument]
+pub async fn index(
+    Extension(state): Extension<Arc<State>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+) -> impl IntoResponse {
+    if !user.can_manage_webhooks() {
+        return make_forbidden(state, FORBIDDEN).await.into_response();
+    }
+
+    let deliveries = match crate::discord_queue::failed_deliveries(&state.mysql_pool).await {
+        Ok(deliveries) => deliveries,
+        Err(error) => {
+            return make_internal_server_error(state, error)
+                .await
+                .into_response();
+        }
+    };
+
+    state.render_template(
+        "discord_deliveries",
+        DiscordDeliveriesTemplate {
+            base: TemplateBase {
+                title: "failed discord deliveries".into(),
+                user: Some(user),
+            },
+
+            deliveries,
+        },
+    )
+}
+
+#[tracing::instrument]
+pub async fn replay(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<State>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    _csrf: CsrfProtected,
+) -> impl IntoResponse {
+    if !user.can_manage_webhooks() {
+        return make_forbidden(state, FORBIDDEN).await.into_response();
+    }
+
+    match crate::discord_queue::replay(&state, id).await {
+        Ok(true) => Redirect::to("/admin/discord-deliveries").into_response(),
+
+        Ok(false) => make_not_found(state, "failed delivery not found")
+            .await
+            .into_response(),
+
+        Err(error) => make_internal_server_error(state, error)
+            .await
+            .into_response(),
+    }
+}
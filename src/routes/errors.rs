@@ -1,7 +1,7 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use axum::{extract::OriginalUri, response::IntoResponse, Extension};
-use http::StatusCode;
+use http::{header::RETRY_AFTER, HeaderValue, StatusCode};
 use serde::Serialize;
 
 use crate::State;
@@ -12,6 +12,19 @@ pub struct ErrorTemplate {
     error_message: String,
 }
 
+pub async fn make_bad_request(state: Arc<State>, message: &str) -> impl IntoResponse {
+    let mut response = state.render_template(
+        "error",
+        ErrorTemplate {
+            error_code: 400,
+            error_message: message.to_owned(),
+        },
+    );
+
+    *response.status_mut() = StatusCode::BAD_REQUEST;
+    response
+}
+
 pub async fn make_unauthorized(state: Arc<State>, message: &str) -> impl IntoResponse {
     let mut response = state.render_template(
         "error",
@@ -51,6 +64,27 @@ pub async fn make_not_found(state: Arc<State>, message: &str) -> impl IntoRespon
     response
 }
 
+pub async fn make_too_many_requests(state: Arc<State>, retry_after: Duration) -> impl IntoResponse {
+    // Rounded up, so that retrying right when told to doesn't get limited again
+    let retry_after_secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+
+    let mut response = state.render_template(
+        "error",
+        ErrorTemplate {
+            error_code: 429,
+            error_message: format!(
+                "you're doing that too much, try again in {retry_after_secs} seconds"
+            ),
+        },
+    );
+
+    *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from(retry_after_secs));
+    response
+}
+
 pub async fn make_internal_server_error(
     state: Arc<State>,
     error: color_eyre::Report,
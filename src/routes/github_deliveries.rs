@@ -0,0 +1,199 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query},
+    response::{IntoResponse, Redirect},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::AuthenticatedUser,
+    csrf::CsrfProtected,
+    github_deliveries::{NewDelivery, StoredDelivery},
+    State,
+};
+
+use super::{
+    errors::{make_forbidden, make_internal_server_error, make_not_found},
+    TemplateBase,
+};
+
+const FORBIDDEN: &str = "You do not have permission to view GitHub deliveries.";
+
+#[derive(Debug, Deserialize)]
+pub struct GithubDeliveriesParams {
+    event: Option<String>,
+}
+
+#[derive(Serialize)]
+struct GithubDeliveriesTemplate {
+    base: TemplateBase,
+    deliveries: Vec<StoredDelivery>,
+    event: Option<String>,
+    duplicates: u64,
+}
+
+#[tracing::instrument]
+pub async fn index(
+    Query(params): Query<GithubDeliveriesParams>,
+    Extension(state): Extension<Arc<State>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+) -> impl IntoResponse {
+    if !user.can_manage_webhooks() {
+        return make_forbidden(state, FORBIDDEN).await.into_response();
+    }
+
+    let event = params.event.filter(|event| !event.is_empty());
+
+    let deliveries = match crate::github_deliveries::recent_deliveries(
+        &state.mysql_pool,
+        event.as_deref(),
+    )
+    .await
+    {
+        Ok(deliveries) => deliveries,
+        Err(error) => {
+            return make_internal_server_error(state, error)
+                .await
+                .into_response();
+        }
+    };
+
+    state.render_template(
+        "github_deliveries",
+        GithubDeliveriesTemplate {
+            base: TemplateBase {
+                title: "github deliveries".into(),
+                user: Some(user),
+            },
+
+            deliveries,
+            event,
+            duplicates: state.github_deliveries.duplicates(),
+        },
+    )
+}
+
+#[derive(Serialize)]
+struct GithubDeliveryTemplate {
+    base: TemplateBase,
+    delivery: StoredDelivery,
+    payload: String,
+}
+
+#[tracing::instrument]
+pub async fn for_delivery(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<State>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+) -> impl IntoResponse {
+    if !user.can_manage_webhooks() {
+        return make_forbidden(state, FORBIDDEN).await.into_response();
+    }
+
+    let (delivery, payload) = match crate::github_deliveries::delivery(&state.mysql_pool, id).await
+    {
+        Ok(Some(delivery)) => delivery,
+        Ok(None) => {
+            return make_not_found(state, "delivery not found")
+                .await
+                .into_response();
+        }
+        Err(error) => {
+            return make_internal_server_error(state, error)
+                .await
+                .into_response();
+        }
+    };
+
+    // Pretty print when we can, but truncated payloads won't parse
+    let payload = serde_json::from_str::<serde_json::Value>(&payload)
+        .ok()
+        .and_then(|payload| serde_json::to_string_pretty(&payload).ok())
+        .unwrap_or(payload);
+
+    state.render_template(
+        "github_delivery",
+        GithubDeliveryTemplate {
+            base: TemplateBase {
+                title: format!("github delivery {}", delivery.delivery_id).into(),
+                user: Some(user),
+            },
+
+            delivery,
+            payload,
+        },
+    )
+}
+
+#[tracing::instrument]
+pub async fn replay(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<State>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    _csrf: CsrfProtected,
+) -> impl IntoResponse {
+    if !user.can_manage_webhooks() {
+        return make_forbidden(state, FORBIDDEN).await.into_response();
+    }
+
+    let (delivery, payload) = match crate::github_deliveries::delivery(&state.mysql_pool, id).await
+    {
+        Ok(Some(delivery)) => delivery,
+        Ok(None) => {
+            return make_not_found(state, "delivery not found")
+                .await
+                .into_response();
+        }
+        Err(error) => {
+            return make_internal_server_error(state, error)
+                .await
+                .into_response();
+        }
+    };
+
+    if !delivery.signature_valid {
+        return make_forbidden(
+            state,
+            "deliveries with invalid signatures can't be replayed",
+        )
+        .await
+        .into_response();
+    }
+
+    if delivery.payload_truncated {
+        return make_forbidden(
+            state,
+            "this delivery's payload was too big to store in full",
+        )
+        .await
+        .into_response();
+    }
+
+    let webhook_body: serde_json::Value = serde_json::from_str(&payload).unwrap_or_default();
+
+    tracing::info!(
+        "{} is replaying github delivery {}",
+        user.ckey,
+        delivery.delivery_id
+    );
+
+    let outcome = super::github_webhook::handle_event(&state, &delivery.event, &webhook_body).await;
+
+    crate::github_deliveries::record(
+        &state,
+        &NewDelivery {
+            delivery_id: &delivery.delivery_id,
+            event_type: &delivery.event,
+            webhook_body: &webhook_body,
+            payload: payload.as_bytes(),
+            signature_valid: true,
+            outcome: format!("replayed by {}: {outcome}", user.ckey),
+            replay_of: Some(delivery.id),
+        },
+    )
+    .await;
+
+    Redirect::to("/admin/github-deliveries").into_response()
+}
@@ -1,198 +1,689 @@
 use std::sync::Arc;
 
 use axum::{response::IntoResponse, Extension};
-use hmac::{Hmac, Mac};
 use http::StatusCode;
 
-fn bytes_to_hex_display(input: &[u8]) -> String {
-    let mut bytes = String::new();
-
-    for byte in input {
-        bytes.push_str(&format!("{:02x}", byte));
-    }
-
-    bytes
+use once_cell::sync::Lazy;
+use sqlx::Row;
+
+use crate::{
+    client_info::ClientInfo,
+    config::{GithubWebhookOptions, GithubWebhookRoute, RepositorySummaries, SummaryRules},
+    relay::{RelayRequest, Verification},
+};
+
+const COLOR_OPENED: u32 = 0x2cbe4e;
+const COLOR_MERGED: u32 = 0x6f42c1;
+const COLOR_CLOSED: u32 = 0xcb2431;
+
+static DEFAULT_SUMMARIES: Lazy<RepositorySummaries> = Lazy::new(RepositorySummaries::default);
+
+fn summaries_for<'a>(
+    options: &'a GithubWebhookOptions,
+    webhook_body: &serde_json::Value,
+) -> &'a RepositorySummaries {
+    webhook_body["repository"]["full_name"]
+        .as_str()
+        .and_then(|repository| options.summaries.get(repository))
+        .unwrap_or(&DEFAULT_SUMMARIES)
 }
 
-pub(self) fn verify_signature(secret: &str, body: &[u8], signature: &str) -> Result<(), String> {
-    let mut hmac: Hmac<sha2::Sha256> =
-        Hmac::new_from_slice(secret.as_bytes()).expect("failed to create hmac");
-    hmac.update(body);
-
-    let signature = signature.replace("sha256=", "");
-    let finalized = bytes_to_hex_display(&hmac.finalize().into_bytes()[..]);
-
-    if finalized == signature {
-        Ok(())
-    } else {
-        Err(finalized)
-    }
+#[derive(Debug, Default, PartialEq)]
+struct Summary {
+    text: String,
+    thumbnail: Option<String>,
 }
 
-pub(self) fn simplify_body(input: &str) -> String {
+/// Cuts the body down to the first of the rules' sections it finds, then strips it down to fit the budget.
+fn simplify_body(input: &str, rules: &SummaryRules) -> Summary {
     let comments_regex = regex::Regex::new(r"(?s)<!--(.*?)-->").unwrap();
     let mut output = comments_regex.replace_all(input, "").to_string();
 
-    let issue_summary_regex = regex::Regex::new(r"(?sm)## Issue Summary.*?(^.+)").unwrap();
-    if let Some(captures) = issue_summary_regex.captures(&output) {
-        output = captures.get(1).unwrap().as_str().to_string();
-    } else {
-        let reproduction_regex = regex::Regex::new(r"(?sm)## Reproduction:.*?(^.+)").unwrap();
-        if let Some(captures) = reproduction_regex.captures(&output) {
+    for section in &rules.sections {
+        let section_regex =
+            regex::Regex::new(&format!(r"(?sm){}.*?(^.+)", regex::escape(section))).unwrap();
+
+        if let Some(captures) = section_regex.captures(&output) {
             output = captures.get(1).unwrap().as_str().to_string();
+            break;
         }
     }
 
     let headers_regex = regex::Regex::new(r"(?m)^#+\s*(.*?)\s*$").unwrap();
     output = headers_regex.replace_all(&output, "**$1**").to_string();
 
-    let images_regex = regex::Regex::new(r"(?s)!\[.*?\]\(.*?\)").unwrap();
+    let images_regex = regex::Regex::new(r"(?s)!\[.*?\]\((.*?)\)").unwrap();
+    let thumbnail = if rules.image_thumbnails {
+        images_regex
+            .captures(&output)
+            .and_then(|captures| captures.get(1))
+            .map(|url| url.as_str().to_owned())
+    } else {
+        None
+    };
     output = images_regex.replace_all(&output, "").to_string();
 
     let mut lines = output.lines().collect::<Vec<_>>();
     lines.retain(|x| !x.is_empty());
 
-    if lines.len() > 4 {
-        lines.truncate(4);
+    if lines.len() > rules.max_lines {
+        lines.truncate(rules.max_lines);
         lines.push("...");
     }
 
-    output = lines.join("\n");
+    let mut text = lines.join("\n").trim().to_owned();
+    truncate_with_ellipsis(&mut text, rules.max_characters);
 
-    output.trim().to_owned()
+    Summary { text, thumbnail }
 }
 
-#[tracing::instrument(skip(body))]
-pub async fn github_webhook(
-    Extension(state): Extension<Arc<crate::State>>,
-    headers: axum::http::header::HeaderMap,
-    body: axum::body::Bytes,
-) -> impl IntoResponse {
-    let Some(signature) = headers.get("X-Hub-Signature-256") else {
-		return (StatusCode::BAD_REQUEST, "missing signature").into_response();
-	};
-
-    let signature = signature.to_str().unwrap_or_default();
-    if let Err(calculated) = verify_signature(
-        &state.config.github_webhook.secret,
-        body.as_ref(),
-        signature,
-    ) {
-        tracing::debug!("invalid signature: {signature}, expected {calculated}");
-        return (StatusCode::BAD_REQUEST, "invalid signature").into_response();
+/// Returns the text under a `## Header`, up until the next header.
+fn section_content<'a>(input: &'a str, header: &str) -> Option<&'a str> {
+    let section_regex = regex::Regex::new(&format!(
+        r"(?sm)^{}[^\n]*\n(.*?)(^#|\z)",
+        regex::escape(header)
+    ))
+    .unwrap();
+
+    Some(section_regex.captures(input)?.get(1)?.as_str())
+}
+
+/// Finds the round ID given in a bug report's `## Round ID:` section.
+fn parse_round_id(input: &str) -> Option<u64> {
+    let comments_regex = regex::Regex::new(r"(?s)<!--(.*?)-->").unwrap();
+    let input = comments_regex.replace_all(input, "");
+
+    let number_regex = regex::Regex::new(r"\d+").unwrap();
+    number_regex
+        .find(section_content(&input, "## Round ID:")?)?
+        .as_str()
+        .parse()
+        .ok()
+}
+
+/// Finds the pull requests listed in a bug report's `## Testmerges:` section, as (number, title).
+fn parse_test_merges(input: &str) -> Vec<(u64, String)> {
+    let comments_regex = regex::Regex::new(r"(?s)<!--(.*?)-->").unwrap();
+    let input = comments_regex.replace_all(input, "");
+
+    let Some(section) = section_content(&input, "## Testmerges:") else {
+        return Vec::new();
+    };
+
+    let test_merge_regex =
+        regex::Regex::new(r"(?m)^\s*[-*]\s*\[(.+)\]\(\S*?/pull/(\d+)\S*\)").unwrap();
+
+    test_merge_regex
+        .captures_iter(section)
+        .filter_map(|captures| Some((captures[2].parse().ok()?, captures[1].to_owned())))
+        .collect()
+}
+
+/// Adds the round a bug report happened in, and the test merges it lists, to its embed.
+async fn add_round_fields(state: &crate::State, embed: &mut serde_json::Value, issue_body: &str) {
+    let urls = &state.config.urls;
+    let mut fields = Vec::new();
+
+    if let Some(round_id) = parse_round_id(issue_body) {
+        let mut lines = vec![format!("[mothbus]({})", urls.mothbus_round(round_id))];
+
+        match sqlx::query(
+            "SELECT server_port, map_name, initialize_datetime, end_datetime FROM round WHERE id = ?",
+        )
+        .bind(round_id)
+        .fetch_optional(&state.mysql_pool)
+        .await
+        {
+            Ok(Some(row)) => {
+                let server_port: Option<u16> = row.try_get("server_port").ok();
+                let map_name: Option<String> = row.try_get("map_name").ok().flatten();
+                let initialize_datetime: Option<chrono::NaiveDateTime> =
+                    row.try_get("initialize_datetime").ok();
+                let end_datetime: Option<chrono::NaiveDateTime> =
+                    row.try_get("end_datetime").ok().flatten();
+
+                let server = server_port.and_then(crate::servers::server_by_port);
+
+                if let (Some(server), Some(initialize_datetime)) = (server, initialize_datetime) {
+                    lines.push(format!(
+                        "[logs]({})",
+                        urls.round_logs(server.name, round_id, initialize_datetime)
+                    ));
+                }
+
+                let mut details = Vec::new();
+
+                if let Some(server) = server {
+                    details.push(server.name.to_owned());
+                }
+
+                if let Some(map_name) = map_name {
+                    details.push(map_name);
+                }
+
+                details.push(match end_datetime {
+                    Some(end_datetime) => format!("ended {}", end_datetime.format("%Y-%m-%d %H:%M")),
+                    None => "still in progress".to_owned(),
+                });
+
+                lines.insert(0, details.join(" · "));
+            }
+
+            Ok(None) => lines.insert(0, "round not found".to_owned()),
+
+            Err(error) => {
+                tracing::error!("failed to look up round {round_id} for issue: {error:#}");
+            }
+        }
+
+        fields.push(serde_json::json!({
+            "name": format!("Round {round_id}"),
+            "value": lines.join("\n"),
+        }));
+    }
+
+    let test_merges = parse_test_merges(issue_body);
+
+    if !test_merges.is_empty() {
+        let mut value = test_merges
+            .iter()
+            .map(|(number, title)| {
+                format!(
+                    "- [#{number}]({}) {title}",
+                    urls.github_pull_request(*number)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        truncate_with_ellipsis(&mut value, 1024);
+
+        fields.push(serde_json::json!({
+            "name": "Test merges",
+            "value": value,
+        }));
+    }
+
+    if !fields.is_empty() {
+        embed["fields"] = serde_json::Value::Array(fields);
+    }
+}
+
+/// Pulls the entries out of a `:cl:` ... `/:cl:` changelog block.
+fn extract_changelog(input: &str) -> Option<String> {
+    let comments_regex = regex::Regex::new(r"(?s)<!--(.*?)-->").unwrap();
+    let input = comments_regex.replace_all(input, "");
+
+    let changelog_regex = regex::Regex::new(r"(?s):cl:[^\n]*\n(.*?)/:cl:").unwrap();
+    let changelog = changelog_regex.captures(&input)?.get(1)?.as_str();
+
+    let lines = changelog
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+fn truncate_with_ellipsis(text: &mut String, max_length: usize) {
+    if text.len() <= max_length {
+        return;
+    }
+
+    let mut cut = max_length.saturating_sub(3);
+    while !text.is_char_boundary(cut) {
+        cut -= 1;
+    }
+
+    text.truncate(cut);
+    text.push_str("...");
+}
+
+fn issue_embed(
+    options: &GithubWebhookOptions,
+    webhook_body: &serde_json::Value,
+) -> Option<serde_json::Value> {
+    if webhook_body["action"].as_str() != Some("opened") {
+        return None;
     }
 
-    let event_type = headers
-        .get("X-GitHub-Event")
-        .map(|x| x.to_str().unwrap_or_default())
+    tracing::debug!("received new issue");
+
+    let summary = simplify_body(
+        webhook_body["issue"]["body"].as_str().unwrap_or_default(),
+        &summaries_for(options, webhook_body).issues,
+    );
+
+    let mut title = format!(
+        "[{}] Issue opened: #{} {}",
+        webhook_body["repository"]["full_name"]
+            .as_str()
+            .unwrap_or_default(),
+        webhook_body["issue"]["number"],
+        webhook_body["issue"]["title"].as_str().unwrap_or_default(),
+    );
+    truncate_with_ellipsis(&mut title, 256);
+
+    Some(serde_json::json!({
+        "title": title,
+
+        "description": summary.text,
+
+        "url": webhook_body["issue"]["html_url"],
+
+        "thumbnail": summary.thumbnail.map(|url| serde_json::json!({ "url": url })),
+
+        "author": {
+            "name": webhook_body["issue"]["user"]["login"],
+            "icon_url": webhook_body["issue"]["user"]["avatar_url"],
+        }
+    }))
+}
+
+fn pull_request_embed(
+    options: &GithubWebhookOptions,
+    webhook_body: &serde_json::Value,
+) -> Option<serde_json::Value> {
+    let pull_request = &webhook_body["pull_request"];
+
+    let (verb, color) = match (
+        webhook_body["action"].as_str(),
+        pull_request["merged"].as_bool().unwrap_or(false),
+    ) {
+        (Some("opened"), _) => ("opened", COLOR_OPENED),
+        (Some("closed"), true) => ("merged", COLOR_MERGED),
+        (Some("closed"), false) => ("closed", COLOR_CLOSED),
+        _ => return None,
+    };
+
+    tracing::debug!("received pull request {verb}");
+
+    let pull_request_body = pull_request["body"].as_str().unwrap_or_default();
+
+    let changelog_block_regex = regex::Regex::new(r"(?s):cl:.*?/:cl:").unwrap();
+    let summary = simplify_body(
+        &changelog_block_regex.replace_all(pull_request_body, ""),
+        &summaries_for(options, webhook_body).pull_requests,
+    );
+
+    let mut title = format!(
+        "[{}] Pull request {verb}: #{} {}",
+        webhook_body["repository"]["full_name"]
+            .as_str()
+            .unwrap_or_default(),
+        pull_request["number"],
+        pull_request["title"].as_str().unwrap_or_default(),
+    );
+    truncate_with_ellipsis(&mut title, 256);
+
+    let mut fields = Vec::new();
+
+    let labels = pull_request["labels"]
+        .as_array()
+        .map(|labels| {
+            labels
+                .iter()
+                .filter_map(|label| label["name"].as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
         .unwrap_or_default();
 
-    tracing::debug!("received github webhook event {event_type}");
+    if !labels.is_empty() {
+        fields.push(serde_json::json!({
+            "name": "Labels",
+            "value": labels,
+            "inline": true,
+        }));
+    }
 
-    if event_type == "issues" {
-        let webhook_body: serde_json::Value =
-            serde_json::from_slice(body.as_ref()).unwrap_or_default();
+    if let (Some(additions), Some(deletions), Some(changed_files)) = (
+        pull_request["additions"].as_u64(),
+        pull_request["deletions"].as_u64(),
+        pull_request["changed_files"].as_u64(),
+    ) {
+        fields.push(serde_json::json!({
+            "name": "Diff",
+            "value": format!(
+                "+{additions} -{deletions} across {changed_files} {}",
+                if changed_files == 1 { "file" } else { "files" },
+            ),
+            "inline": true,
+        }));
+    }
 
-        if webhook_body.get("action").map(|x| x.as_str()) == Some(Some("opened")) {
-            tracing::debug!("received new issue");
+    if let Some(mut changelog) = extract_changelog(pull_request_body) {
+        truncate_with_ellipsis(&mut changelog, 1024);
 
-            let mut body =
-                simplify_body(webhook_body["issue"]["body"].as_str().unwrap_or_default());
+        fields.push(serde_json::json!({
+            "name": "Changelog",
+            "value": changelog,
+        }));
+    }
 
-            if body.len() > 500 {
-                body.truncate(500);
-                body.push_str("...");
+    Some(serde_json::json!({
+        "title": title,
+
+        "description": summary.text,
+
+        "url": pull_request["html_url"],
+
+        "thumbnail": summary.thumbnail.map(|url| serde_json::json!({ "url": url })),
+
+        "color": color,
+
+        "author": {
+            "name": pull_request["user"]["login"],
+            "icon_url": pull_request["user"]["avatar_url"],
+        },
+
+        "fields": fields,
+    }))
+}
+
+#[derive(Debug, PartialEq)]
+struct Destination<'a> {
+    discord_url: &'a str,
+    mention_roles: Vec<&'a str>,
+}
+
+fn list_matches(list: &[String], value: Option<&str>) -> bool {
+    list.is_empty() || value.is_some_and(|value| list.iter().any(|item| item == value))
+}
+
+fn route_matches(
+    route: &GithubWebhookRoute,
+    event_type: &str,
+    webhook_body: &serde_json::Value,
+) -> bool {
+    // Issues and pull requests are the only events we send, and they look the same for our purposes
+    let subject = if webhook_body["issue"].is_object() {
+        &webhook_body["issue"]
+    } else {
+        &webhook_body["pull_request"]
+    };
+
+    let labels = subject["labels"]
+        .as_array()
+        .map(|labels| {
+            labels
+                .iter()
+                .filter_map(|label| label["name"].as_str())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    list_matches(
+        &route.repositories,
+        webhook_body["repository"]["full_name"].as_str(),
+    ) && list_matches(&route.events, Some(event_type))
+        && list_matches(&route.actions, webhook_body["action"].as_str())
+        && (route.labels.is_empty()
+            || labels
+                .iter()
+                .any(|label| route.labels.iter().any(|x| x == label)))
+        && list_matches(&route.authors, subject["user"]["login"].as_str())
+}
+
+fn destinations<'a>(
+    options: &'a GithubWebhookOptions,
+    event_type: &str,
+    webhook_body: &serde_json::Value,
+) -> Vec<Destination<'a>> {
+    let mut destinations: Vec<Destination> = Vec::new();
+
+    for route in &options.routes {
+        if !route_matches(route, event_type, webhook_body) {
+            continue;
+        }
+
+        // Routes to the same channel send one message, mentioning everyone either route would
+        match destinations
+            .iter_mut()
+            .find(|destination| destination.discord_url == route.discord_url)
+        {
+            Some(destination) => {
+                if let Some(role) = route.mention_role.as_deref() {
+                    if !destination.mention_roles.contains(&role) {
+                        destination.mention_roles.push(role);
+                    }
+                }
             }
 
-            let mut title = format!(
-                "[{}] Issue opened: #{} {}",
+            None => destinations.push(Destination {
+                discord_url: &route.discord_url,
+                mention_roles: route.mention_role.as_deref().into_iter().collect(),
+            }),
+        }
+    }
+
+    if destinations.is_empty() {
+        if let Some(discord_url) = &options.discord_url {
+            destinations.push(Destination {
+                discord_url,
+                mention_roles: Vec::new(),
+            });
+        }
+    }
+
+    destinations
+}
+
+fn github_embed(
+    options: &GithubWebhookOptions,
+    event_type: &str,
+    webhook_body: &serde_json::Value,
+) -> Option<serde_json::Value> {
+    match event_type {
+        "issues" => issue_embed(options, webhook_body),
+        "pull_request" => pull_request_embed(options, webhook_body),
+        _ => None,
+    }
+}
+
+pub enum EventOutcome {
+    Ignored,
+    Queued(usize),
+    Failed(String),
+}
+
+impl std::fmt::Display for EventOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventOutcome::Ignored => write!(f, "ignored"),
+            EventOutcome::Queued(count) => write!(f, "queued for {count} destination(s)"),
+            EventOutcome::Failed(error) => write!(f, "failed: {error}"),
+        }
+    }
+}
+
+/// Acts on an event that has already been verified. Also used to replay stored deliveries.
+pub async fn handle_event(
+    state: &crate::State,
+    event_type: &str,
+    webhook_body: &serde_json::Value,
+) -> EventOutcome {
+    if event_type == "pull_request" {
+        if let Some(pull_request) =
+            crate::pull_requests::PullRequest::from_webhook(&webhook_body["pull_request"])
+        {
+            if let Err(error) = crate::pull_requests::store_pull_request(
+                &state.mysql_pool,
                 webhook_body["repository"]["full_name"]
                     .as_str()
                     .unwrap_or_default(),
-                webhook_body["issue"]["number"],
-                webhook_body["issue"]["title"].as_str().unwrap_or_default(),
-            );
-
-            if title.bytes().len() > 256 {
-                tracing::debug!("title too long, truncating");
-                title.truncate(250);
-                title.push_str("...");
+                &pull_request,
+            )
+            .await
+            {
+                tracing::error!("failed to store pull request: {error:#}");
             }
+        }
+    }
 
-            if let Err(error) = reqwest::Client::new()
-                .post(&state.config.github_webhook.discord_url)
-                .json(&serde_json::json!({
-                    "embeds": [{
-                        "title": title,
+    let Some(mut embed) = github_embed(&state.config.github_webhook, event_type, webhook_body)
+    else {
+        return EventOutcome::Ignored;
+    };
 
-                        "description": body,
+    if event_type == "issues" {
+        add_round_fields(
+            state,
+            &mut embed,
+            webhook_body["issue"]["body"].as_str().unwrap_or_default(),
+        )
+        .await;
+    }
 
-                        "url": webhook_body["issue"]["html_url"],
+    let destinations = destinations(&state.config.github_webhook, event_type, webhook_body);
 
-                        "author": {
-                            "name": webhook_body["issue"]["user"]["login"],
-                            "icon_url": webhook_body["issue"]["user"]["avatar_url"],
-                        }
-                    }],
-                }))
-                .send()
-                .await
-            {
-                tracing::error!("failed to send discord webhook: {error:#}");
-                return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "failed to send discord webhook",
-                )
-                    .into_response();
-            }
+    for destination in &destinations {
+        if let Err(error) = crate::discord_queue::enqueue(
+            state,
+            destination.discord_url,
+            &crate::discord_queue::discord_message(&embed, &destination.mention_roles),
+        )
+        .await
+        {
+            tracing::error!("failed to queue discord webhook: {error:#}");
+            return EventOutcome::Failed(format!("{error:#}"));
         }
     }
 
-    (StatusCode::NO_CONTENT, "").into_response()
+    EventOutcome::Queued(destinations.len())
+}
+
+#[tracing::instrument(skip(body))]
+pub async fn github_webhook(
+    Extension(state): Extension<Arc<crate::State>>,
+    client_info: ClientInfo,
+    headers: axum::http::header::HeaderMap,
+    body: axum::body::Bytes,
+) -> impl IntoResponse {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .map(|x| x.to_str().unwrap_or_default())
+            .unwrap_or_default()
+    };
+
+    let event_type = header("X-GitHub-Event");
+    let webhook_body: serde_json::Value = serde_json::from_slice(body.as_ref()).unwrap_or_default();
+
+    let mut delivery = crate::github_deliveries::NewDelivery {
+        delivery_id: header("X-GitHub-Delivery"),
+        event_type,
+        webhook_body: &webhook_body,
+        payload: body.as_ref(),
+        signature_valid: false,
+        outcome: String::new(),
+        replay_of: None,
+    };
+
+    let request = RelayRequest {
+        headers: &headers,
+        body: body.as_ref(),
+        client_info,
+    };
+
+    let verification = Verification::HmacSha256 {
+        secret: state.config.github_webhook.secret.clone(),
+        header: "X-Hub-Signature-256".to_owned(),
+    };
+
+    if let Err(error) = verification.verify(&request) {
+        crate::github_deliveries::record(
+            &state,
+            &crate::github_deliveries::NewDelivery {
+                outcome: error.clone(),
+                ..delivery
+            },
+        )
+        .await;
+
+        return (StatusCode::BAD_REQUEST, error).into_response();
+    }
+
+    tracing::debug!("received github webhook event {event_type}");
+
+    delivery.signature_valid = true;
+
+    if !state.github_deliveries.claim(delivery.delivery_id).await {
+        tracing::info!(
+            "ignoring duplicate github delivery {} ({} duplicates since startup)",
+            delivery.delivery_id,
+            state.github_deliveries.duplicates(),
+        );
+
+        delivery.outcome = "duplicate".to_owned();
+        crate::github_deliveries::record(&state, &delivery).await;
+        return (StatusCode::OK, "duplicate delivery").into_response();
+    }
+
+    let outcome = handle_event(&state, event_type, &webhook_body).await;
+
+    if let EventOutcome::Failed(_) = outcome {
+        state.github_deliveries.release(delivery.delivery_id).await;
+    }
+
+    delivery.outcome = outcome.to_string();
+    crate::github_deliveries::record(&state, &delivery).await;
+
+    match outcome {
+        EventOutcome::Failed(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed to queue discord webhook",
+        )
+            .into_response(),
+
+        EventOutcome::Ignored | EventOutcome::Queued(_) => {
+            (StatusCode::NO_CONTENT, "").into_response()
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn verify_signature_simple() {
-        assert_eq!(
-            verify_signature(
-                "It's a Secret to Everybody",
-                b"Hello, World!",
-                "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17"
-            ),
-            Ok(())
-        );
+    enum Expected {
+        Exactly(&'static str),
+        StartsWith(&'static str),
     }
 
-    #[test]
-    fn simplify_body_headers() {
-        assert_eq!(simplify_body("# Header\nText"), "**Header**\nText")
+    struct Fixture {
+        name: &'static str,
+        rules: fn() -> SummaryRules,
+        input: &'static str,
+        expected: Expected,
+        thumbnail: Option<&'static str>,
     }
 
-    #[test]
-    fn simplify_body_keep() {
-        assert_eq!(
-            simplify_body("**bold** *italics* __underline__ **__bold and underline__**"),
-            "**bold** *italics* __underline__ **__bold and underline__**"
-        )
+    fn tgstation_issue() -> SummaryRules {
+        RepositorySummaries::default().issues
     }
 
-    #[test]
-    fn simplify_body_comments() {
-        assert_eq!(simplify_body("<!-- comment\nwith new lines -->"), "")
+    fn tgstation_pull_request() -> SummaryRules {
+        RepositorySummaries::default().pull_requests
     }
 
-    #[test]
-    fn simplify_images() {
-        assert_eq!(simplify_body("![image](https://google.com)"), "");
+    fn maps_issue() -> SummaryRules {
+        SummaryRules {
+            sections: vec![
+                "## Description".to_owned(),
+                "## Steps to reproduce".to_owned(),
+            ],
+            max_lines: 2,
+            max_characters: 60,
+            image_thumbnails: true,
+        }
     }
 
-    #[test]
-    fn simplify_template() {
-        let simplified_template = simplify_body(indoc::indoc! {"
+    const TGSTATION_ISSUE_TEMPLATE: &str = indoc::indoc! {"
         Reporting client version: 515.1620
 
         <!-- Write **BELOW** The Headers and **ABOVE** The comments else it may not be viewable -->
@@ -218,11 +709,239 @@ mod tests {
         <!-- **For Admins:** Oddities induced by var-edits and other admin tools are not necessarily bugs. Verify that your issues occur under regular circumstances before reporting them. -->
         
         <!-- If you are reporting a runtime error you must include the runtime in your report or your report will be closed. -->
-        "});
+        "};
+
+    const FIXTURES: &[Fixture] = &[
+        Fixture {
+            name: "headers",
+            rules: tgstation_issue,
+            input: "# Header\nText",
+            expected: Expected::Exactly("**Header**\nText"),
+            thumbnail: None,
+        },
+        Fixture {
+            name: "keep formatting",
+            rules: tgstation_issue,
+            input: "**bold** *italics* __underline__ **__bold and underline__**",
+            expected: Expected::Exactly(
+                "**bold** *italics* __underline__ **__bold and underline__**",
+            ),
+            thumbnail: None,
+        },
+        Fixture {
+            name: "comments",
+            rules: tgstation_issue,
+            input: "<!-- comment\nwith new lines -->",
+            expected: Expected::Exactly(""),
+            thumbnail: None,
+        },
+        Fixture {
+            name: "images",
+            rules: tgstation_issue,
+            input: "![image](https://google.com)",
+            expected: Expected::Exactly(""),
+            thumbnail: None,
+        },
+        Fixture {
+            name: "tgstation issue template",
+            rules: tgstation_issue,
+            input: TGSTATION_ISSUE_TEMPLATE,
+            expected: Expected::StartsWith("By rightclicking"),
+            thumbnail: None,
+        },
+        Fixture {
+            name: "tgstation pull request template",
+            rules: tgstation_pull_request,
+            input: indoc::indoc! {"
+            <!-- Write **BELOW** The Headers and **ABOVE** The comments else it may not be viewable. -->
+
+            ## About The Pull Request
+
+            Eggs no longer duplicate their reagents when put into a soup pot.
+
+            ## Why It's Good For The Game
+
+            Bugs are bad.
+            "},
+            expected: Expected::StartsWith("Eggs no longer"),
+            thumbnail: None,
+        },
+        Fixture {
+            name: "maps issue template",
+            rules: maps_issue,
+            input: indoc::indoc! {"
+            ## Map
+            MetaStation
+
+            ## Description
+            ![screenshot](https://example.com/meta.png)
+            There is a window missing in the bridge.
+            Space wind pulls everyone out of it.
+            It's been like this since the last map update.
+            "},
+            expected: Expected::Exactly(
+                "There is a window missing in the bridge.\nSpace wind pulls...",
+            ),
+            thumbnail: Some("https://example.com/meta.png"),
+        },
+    ];
+
+    #[test]
+    fn simplify_body_fixtures() {
+        for fixture in FIXTURES {
+            let summary = simplify_body(fixture.input, &(fixture.rules)());
+
+            match fixture.expected {
+                Expected::Exactly(expected) => assert_eq!(
+                    summary.text, expected,
+                    "fixture \"{}\" didn't match",
+                    fixture.name
+                ),
+
+                Expected::StartsWith(expected) => assert!(
+                    summary.text.starts_with(expected),
+                    "fixture \"{}\" doesn't start with {expected:?}:\n{}",
+                    fixture.name,
+                    summary.text,
+                ),
+            }
 
-        assert!(
-            simplified_template.starts_with("By rightclicking"),
-            "Template doesn't start at reproduction:\n{simplified_template}",
+            assert_eq!(
+                summary.thumbnail.as_deref(),
+                fixture.thumbnail,
+                "fixture \"{}\" had the wrong thumbnail",
+                fixture.name
+            );
+        }
+    }
+
+    #[test]
+    fn parse_round_id_template() {
+        assert_eq!(parse_round_id(TGSTATION_ISSUE_TEMPLATE), Some(219951));
+        assert_eq!(parse_round_id("## Round ID:\n\n## Testmerges:\n"), None);
+    }
+
+    #[test]
+    fn parse_test_merges_template() {
+        assert_eq!(
+            parse_test_merges(TGSTATION_ISSUE_TEMPLATE),
+            vec![
+                (79968, "Attack chain refactoring: Broadening `tool_act` into `item_interact`, moving some item interactions to... `atom/item_interact` / `item/interact_with_atom`".to_owned()),
+                (79854, "Changes Virology Rather Than Killing It".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn extract_changelog_simple() {
+        assert_eq!(
+            extract_changelog(indoc::indoc! {"
+            ## Changelog
+            <!-- If your PR modifies aspects of the game that can be concretely observed by players or admins you should add a changelog. -->
+            :cl: Mothblocks
+            fix: Eggs no longer duplicate their reagents in soup pots.
+            qol: Soup pots show their contents.
+            /:cl:
+            "})
+            .as_deref(),
+            Some("fix: Eggs no longer duplicate their reagents in soup pots.\nqol: Soup pots show their contents.")
+        );
+    }
+
+    #[test]
+    fn extract_changelog_missing() {
+        assert_eq!(
+            extract_changelog("## Changelog\nNothing player facing"),
+            None
+        );
+    }
+
+    #[test]
+    fn truncate_with_ellipsis_char_boundary() {
+        let mut text = "🦋🦋🦋".to_owned();
+        truncate_with_ellipsis(&mut text, 8);
+        assert_eq!(text, "🦋...");
+    }
+
+    #[test]
+    fn destinations_by_label() {
+        let options = GithubWebhookOptions {
+            discord_url: Some("https://discord/default".to_owned()),
+            routes: vec![GithubWebhookRoute {
+                discord_url: "https://discord/coders".to_owned(),
+                mention_role: Some("1234".to_owned()),
+                repositories: vec!["tgstation/tgstation".to_owned()],
+                events: vec!["issues".to_owned()],
+                labels: vec!["Runtime".to_owned()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let runtime_issue = serde_json::json!({
+            "action": "opened",
+            "repository": { "full_name": "tgstation/tgstation" },
+            "issue": {
+                "labels": [{ "name": "Bug" }, { "name": "Runtime" }],
+                "user": { "login": "Mothblocks" },
+            },
+        });
+
+        assert_eq!(
+            destinations(&options, "issues", &runtime_issue),
+            vec![Destination {
+                discord_url: "https://discord/coders",
+                mention_roles: vec!["1234"],
+            }]
+        );
+
+        assert_eq!(
+            destinations(&options, "pull_request", &runtime_issue),
+            vec![Destination {
+                discord_url: "https://discord/default",
+                mention_roles: Vec::new(),
+            }]
+        );
+
+        let other_issue = serde_json::json!({
+            "action": "opened",
+            "repository": { "full_name": "tgstation/tgstation" },
+            "issue": {
+                "labels": [{ "name": "Bug" }],
+                "user": { "login": "Mothblocks" },
+            },
+        });
+
+        assert_eq!(
+            destinations(&options, "issues", &other_issue),
+            vec![Destination {
+                discord_url: "https://discord/default",
+                mention_roles: Vec::new(),
+            }]
+        );
+
+        // A second route to the same channel adds its mention, rather than being dropped
+        assert_eq!(
+            destinations(
+                &GithubWebhookOptions {
+                    routes: vec![
+                        options.routes[0].clone(),
+                        GithubWebhookRoute {
+                            discord_url: "https://discord/coders".to_owned(),
+                            mention_role: Some("5678".to_owned()),
+                            labels: vec!["Bug".to_owned()],
+                            ..Default::default()
+                        },
+                    ],
+                    ..Default::default()
+                },
+                "issues",
+                &runtime_issue,
+            ),
+            vec![Destination {
+                discord_url: "https://discord/coders",
+                mention_roles: vec!["1234", "5678"],
+            }]
         );
     }
 }
@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use axum::{extract::Path, response::IntoResponse, Extension};
+use http::StatusCode;
+
+use crate::{client_info::ClientInfo, relay::RelayRequest, State};
+
+#[tracing::instrument(skip(body))]
+pub async fn relay(
+    Path(source): Path<String>,
+    Extension(state): Extension<Arc<State>>,
+    client_info: ClientInfo,
+    headers: axum::http::header::HeaderMap,
+    body: axum::body::Bytes,
+) -> impl IntoResponse {
+    let Some(hook) = state.hooks.get(&source) else {
+        return (StatusCode::NOT_FOUND, "unknown hook").into_response();
+    };
+
+    let request = RelayRequest {
+        headers: &headers,
+        body: body.as_ref(),
+        client_info,
+    };
+
+    if let Err(error) = hook.source.verify(&request) {
+        tracing::debug!("rejected {source} hook: {error}");
+        return (StatusCode::BAD_REQUEST, error).into_response();
+    }
+
+    let payload: serde_json::Value = match serde_json::from_slice(body.as_ref()) {
+        Ok(payload) => payload,
+        Err(error) => {
+            return (StatusCode::BAD_REQUEST, format!("invalid json: {error}")).into_response();
+        }
+    };
+
+    let Some(embed) = hook.source.embed(&request, &payload, &state.config.urls) else {
+        tracing::debug!("{source} hook had nothing to relay");
+        return (StatusCode::NO_CONTENT, "").into_response();
+    };
+
+    if let Err(error) = crate::discord_queue::enqueue(
+        &state,
+        &hook.discord_url,
+        &crate::discord_queue::discord_message(&embed, hook.mention_role.as_deref().as_slice()),
+    )
+    .await
+    {
+        tracing::error!("failed to queue {source} hook: {error:#}");
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed to queue discord webhook",
+        )
+            .into_response();
+    }
+
+    (StatusCode::NO_CONTENT, "").into_response()
+}
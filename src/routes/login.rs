@@ -1,24 +1,128 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::sync::Arc;
 
 use axum::{
-    extract::{ConnectInfo, Path, Query},
-    response::{AppendHeaders, IntoResponse, Redirect},
+    extract::{Path, Query},
+    response::{IntoResponse, Redirect, Response},
     Extension,
 };
-use color_eyre::eyre::Context;
-use http::{header::SET_COOKIE, StatusCode};
-use serde::Deserialize;
-
-use crate::config::OAuth2Options;
-
-#[tracing::instrument]
-pub async fn index(Extension(state): Extension<Arc<crate::State>>) -> impl IntoResponse {
-    // TODO: &state URL parameter
-    Redirect::to(&format!(
-		"https://tgstation13.org/phpBB/app.php/tgapi/oauth/auth?response_type=code&client_id={}&redirect_uri={}",
-		state.config.oauth2.client_id,
-		state.config.oauth2.redirect_uri,
-	))
+use axum_extra::extract::cookie::CookieJar;
+use http::{header::USER_AGENT, HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client_info::ClientInfo, cookies, csrf::constant_time_eq, login_providers::LoginProvider,
+    session, session_store::SessionClient,
+};
+
+use super::TemplateBase;
+
+#[derive(Debug, Deserialize)]
+pub struct LoginQuery {
+    return_to: Option<String>,
+}
+
+#[derive(Serialize)]
+struct LoginTemplate {
+    base: TemplateBase,
+    providers: Vec<LoginProviderView>,
+}
+
+#[derive(Serialize)]
+struct LoginProviderView {
+    display_name: &'static str,
+    url: String,
+}
+
+/// Goes straight to the only provider, or lets the player pick when there are several.
+#[tracing::instrument(skip(cookie_jar))]
+pub async fn index(
+    Extension(state): Extension<Arc<crate::State>>,
+    Query(query): Query<LoginQuery>,
+    client_info: ClientInfo,
+    cookie_jar: CookieJar,
+) -> impl IntoResponse {
+    if let [provider] = state.login_providers.as_slice() {
+        return start_login(
+            Arc::clone(&state),
+            provider.as_ref(),
+            query,
+            client_info,
+            cookie_jar,
+        )
+        .await
+        .into_response();
+    }
+
+    let return_to = session::sanitize_return_to(query.return_to.as_deref());
+
+    state.render_template(
+        "login",
+        LoginTemplate {
+            base: TemplateBase {
+                title: "login".into(),
+                user: None,
+            },
+
+            providers: state
+                .login_providers
+                .iter()
+                .map(|provider| LoginProviderView {
+                    display_name: provider.display_name(),
+                    url: format!(
+                        "/login/{}?{}",
+                        provider.name(),
+                        form_urlencoded::Serializer::new(String::new())
+                            .append_pair("return_to", &return_to)
+                            .finish()
+                    ),
+                })
+                .collect(),
+        },
+    )
+}
+
+#[tracing::instrument(skip(cookie_jar))]
+pub async fn login_with(
+    Path(provider): Path<String>,
+    Extension(state): Extension<Arc<crate::State>>,
+    Query(query): Query<LoginQuery>,
+    client_info: ClientInfo,
+    cookie_jar: CookieJar,
+) -> impl IntoResponse {
+    match state.login_provider(&provider) {
+        Some(provider) => start_login(Arc::clone(&state), provider, query, client_info, cookie_jar)
+            .await
+            .into_response(),
+
+        None => super::errors::make_not_found(state, "no such way to login")
+            .await
+            .into_response(),
+    }
+}
+
+async fn start_login(
+    state: Arc<crate::State>,
+    provider: &dyn LoginProvider,
+    query: LoginQuery,
+    client_info: ClientInfo,
+    cookie_jar: CookieJar,
+) -> impl IntoResponse {
+    let return_to = session::sanitize_return_to(query.return_to.as_deref());
+
+    let (oauth_state, nonce) = match session::new_oauth_state_token(&return_to) {
+        Ok(oauth_state) => oauth_state,
+        Err(error) => {
+            return super::errors::make_internal_server_error(state, error)
+                .await
+                .into_response();
+        }
+    };
+
+    (
+        cookies::add_oauth_nonce(cookie_jar, nonce, client_info),
+        Redirect::to(&provider.authorize_url(&oauth_state)),
+    )
+        .into_response()
 }
 
 #[derive(Debug, Deserialize)]
@@ -26,23 +130,60 @@ pub async fn index(Extension(state): Extension<Arc<crate::State>>) -> impl IntoR
 pub enum OAuthQuery {
     Success {
         code: String,
+        state: String,
     },
 
     Error {
         error: String,
-        #[serde(rename = "errordesc")]
+        #[serde(rename = "errordesc", alias = "error_description", default)]
         error_description: String,
     },
 }
 
-// TODO: Pass &state, and validate it here
-#[tracing::instrument]
+/// Where the first provider sends players back to, from before there were several.
+#[tracing::instrument(skip(cookie_jar, headers))]
 pub async fn oauth(
     Extension(state): Extension<Arc<crate::State>>,
     Query(query): Query<OAuthQuery>,
+    client_info: ClientInfo,
+    headers: HeaderMap,
+    cookie_jar: CookieJar,
+) -> impl IntoResponse {
+    let provider = state.login_providers[0].as_ref();
+
+    finish_login(&state, provider, query, client_info, &headers, cookie_jar).await
+}
+
+#[tracing::instrument(skip(cookie_jar, headers))]
+pub async fn oauth_for(
+    Path(provider): Path<String>,
+    Extension(state): Extension<Arc<crate::State>>,
+    Query(query): Query<OAuthQuery>,
+    client_info: ClientInfo,
+    headers: HeaderMap,
+    cookie_jar: CookieJar,
 ) -> impl IntoResponse {
-    let code = match query {
-        OAuthQuery::Success { code } => code,
+    let Some(provider) = state.login_provider(&provider) else {
+        return super::errors::make_not_found(Arc::clone(&state), "no such way to login")
+            .await
+            .into_response();
+    };
+
+    finish_login(&state, provider, query, client_info, &headers, cookie_jar).await
+}
+
+async fn finish_login(
+    state: &Arc<crate::State>,
+    provider: &dyn LoginProvider,
+    query: OAuthQuery,
+    client_info: ClientInfo,
+    headers: &HeaderMap,
+    cookie_jar: CookieJar,
+) -> Response {
+    let state = Arc::clone(state);
+
+    let (code, oauth_state) = match query {
+        OAuthQuery::Success { code, state } => (code, state),
         OAuthQuery::Error {
             error,
             error_description,
@@ -56,131 +197,80 @@ pub async fn oauth(
         }
     };
 
-    let ckey = match ckey_for_auth(&code, &state.config.oauth2).await {
-        Ok(Some(ckey)) => ckey,
-
-        Ok(None) => {
-            return super::errors::make_unauthorized(state, "forum account has no ckey")
-                .await
-                .into_response();
+    // The state must be one we signed, for this browser, and not used before
+    let oauth_state = match session::oauth_state_from_token(&oauth_state) {
+        Some(oauth_state)
+            if cookie_jar.get(cookies::OAUTH_NONCE).is_some_and(|nonce| {
+                constant_time_eq(nonce.value().as_bytes(), oauth_state.nonce.as_bytes())
+            }) =>
+        {
+            oauth_state
         }
 
-        Err(error) => {
-            return super::errors::make_internal_server_error(state, error)
-                .await
-                .into_response();
+        _ => {
+            return super::errors::make_unauthorized(
+                state,
+                "login expired or was started somewhere else, try logging in again",
+            )
+            .await
+            .into_response();
         }
     };
 
-    tracing::debug!("logged in as {}", ckey);
-
-    login_as(state, &ckey).await.into_response()
-}
-
-async fn ckey_for_auth(
-    code: &str,
-    oauth_config: &OAuth2Options,
-) -> color_eyre::Result<Option<String>> {
-    #[derive(Deserialize)]
-    #[serde(untagged)]
-    enum AuthorizationCodeResponse {
-        Success {
-            access_token: String,
-        },
-
-        Error {
-            error: String,
-            error_description: String,
-        },
+    if !state.claim_oauth_state(&oauth_state.nonce).await {
+        return super::errors::make_unauthorized(state, "this login has already been used")
+            .await
+            .into_response();
     }
 
-    let client = reqwest::ClientBuilder::new()
-        .user_agent("moth.fans")
-        .build()
-        .context("couldn't create http client")?;
-
-    tracing::debug!("requesting access token for {code}");
-
-    let authorization_token_response_text = client
-        .post("https://tgstation13.org/phpBB/app.php/tgapi/oauth/token")
-        .form(&[
-            ("grant_type", "authorization_code"),
-            ("code", code),
-            ("client_id", &oauth_config.client_id),
-            ("client_secret", &oauth_config.client_secret),
-            ("redirect_uri", &oauth_config.redirect_uri),
-        ])
-        .send()
-        .await
-        .context("error sending request to oauth server")?
-        .text()
-        .await
-        .context("error getting response text for authorization token")?;
-
-    let access_token = match serde_json::from_str(&authorization_token_response_text) {
-        Ok(AuthorizationCodeResponse::Success { access_token }) => access_token,
-
-        Ok(AuthorizationCodeResponse::Error {
-            error,
-            error_description,
-        }) => {
-            tracing::error!("authorization response failure: {error_description} ({error})");
+    let ckey = match provider.ckey_for_code(&state, &code).await {
+        Ok(Some(ckey)) => ckey,
 
-            return Err(color_eyre::eyre::eyre!(
-                "authorization response failure: {error_description} ({error})"
-            ));
+        Ok(None) => {
+            return super::errors::make_unauthorized(
+                state,
+                &format!("{} has no ckey linked", provider.display_name()),
+            )
+            .await
+            .into_response();
         }
 
         Err(error) => {
-            tracing::error!(
-                "invalid authorization token response body: {authorization_token_response_text}"
-            );
-
-            return Err(error).context("error parsing authorization token response");
+            return super::errors::make_internal_server_error(state, error)
+                .await
+                .into_response();
         }
     };
 
-    #[derive(Deserialize)]
-    struct UserResponse {
-        byond_ckey: Option<String>,
-    }
-
-    tracing::debug!("requesting user info for {access_token}");
-
-    let tgapi_user_response_text = client
-        .get("https://tgstation13.org/phpBB/app.php/tgapi/user/me")
-        .header("Authorization", format!("Bearer {access_token}"))
-        .send()
-        .await
-        .context("error sending request to oauth server")?
-        .text()
-        .await
-        .context("error getting response text for user")?;
-
-    let user_response: UserResponse = match serde_json::from_str(&tgapi_user_response_text) {
-        Ok(user_response) => user_response,
-        Err(error) => {
-            tracing::error!("invalid user response body: {tgapi_user_response_text}");
-
-            return Err(error).context("error parsing user response");
-        }
-    };
+    tracing::debug!("logged in as {} through {}", ckey, provider.name());
 
-    Ok(user_response.byond_ckey)
+    login_as(
+        state,
+        &ckey,
+        client_info,
+        headers,
+        // The nonce has done its job
+        cookies::remove_oauth_nonce(cookie_jar),
+        &session::sanitize_return_to(Some(&oauth_state.return_to)),
+    )
+    .await
+    .into_response()
 }
 
-#[tracing::instrument]
+#[tracing::instrument(skip(headers, cookie_jar))]
 pub async fn mock_login(
     Extension(state): Extension<Arc<crate::State>>,
     Path(ckey): Path<String>,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    client_info: ClientInfo,
+    headers: HeaderMap,
+    cookie_jar: CookieJar,
 ) -> impl IntoResponse {
     if !state.config.mock_login {
         return (StatusCode::FORBIDDEN, "mock logins are disabled").into_response();
     }
 
     // Extra security
-    if !addr.ip().is_loopback() {
+    if !client_info.is_local() {
         return (
             StatusCode::FORBIDDEN,
             "mock logins are only allowed on localhost",
@@ -188,11 +278,28 @@ pub async fn mock_login(
             .into_response();
     }
 
-    login_as(state, &ckey).await.into_response()
+    login_as(state, &ckey, client_info, &headers, cookie_jar, "/")
+        .await
+        .into_response()
 }
 
-async fn login_as(state: Arc<crate::State>, ckey: &str) -> impl IntoResponse {
-    let session_jwt = match state.create_session_for(ckey).await {
+async fn login_as(
+    state: Arc<crate::State>,
+    ckey: &str,
+    client_info: ClientInfo,
+    headers: &HeaderMap,
+    cookie_jar: CookieJar,
+    return_to: &str,
+) -> impl IntoResponse {
+    let client = SessionClient {
+        ip: client_info.ip.to_string(),
+        user_agent: headers
+            .get(USER_AGENT)
+            .and_then(|user_agent| user_agent.to_str().ok())
+            .map(str::to_owned),
+    };
+
+    let session_jwt = match state.create_session_for(ckey, &client).await {
         Ok(session_jwt) => session_jwt,
         Err(error) => {
             return (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response();
@@ -200,11 +307,227 @@ async fn login_as(state: Arc<crate::State>, ckey: &str) -> impl IntoResponse {
     };
 
     (
-        AppendHeaders([(
-            SET_COOKIE,
-            format!("session_jwt={session_jwt}; SameSite=None; Secure; Path=/; Max-Age=31536000",),
-        )]),
-        Redirect::to("/"),
+        cookies::add_session(cookie_jar, session_jwt, client_info),
+        Redirect::to(return_to),
     )
         .into_response()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{
+        body::{Body, HttpBody},
+        extract::ConnectInfo,
+        routing::get,
+        Router,
+    };
+    use http::{
+        header::{LOCATION, SET_COOKIE},
+        Request,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        login_providers::fake::{FakeAccount, FakeOAuthServer},
+        State,
+    };
+
+    const MOTHBUS_URL: &str = "http://mothbus.test";
+
+    fn test_app(fake: &FakeOAuthServer, client_secret: Option<&str>) -> (Router, Arc<State>) {
+        crate::signing_keys::load_for_tests();
+
+        let mut config = State::test_config("");
+
+        config.oauth2 = fake.options(&format!("{MOTHBUS_URL}/oauth"));
+        if let Some(client_secret) = client_secret {
+            config.oauth2.client_secret = client_secret.to_owned();
+        }
+
+        let state = Arc::new(State::for_tests(config));
+
+        let app = Router::new()
+            .route("/login", get(index))
+            .route("/oauth", get(oauth))
+            .layer(Extension(Arc::clone(&state)));
+
+        (app, state)
+    }
+
+    async fn request(app: &Router, uri: &str, cookie: Option<&str>) -> Response {
+        let mut request = Request::get(uri);
+        if let Some(cookie) = cookie {
+            request = request.header(http::header::COOKIE, cookie);
+        }
+
+        let mut request = request.body(Body::empty()).unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 1234))));
+
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    async fn body_text(response: Response) -> String {
+        let mut body = response.into_body();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+
+        String::from_utf8(bytes).unwrap()
+    }
+
+    fn location(response: &Response) -> String {
+        response.headers()[LOCATION].to_str().unwrap().to_owned()
+    }
+
+    /// Goes through `/login` and the fake provider, returning where the provider sent
+    /// the player back to on mothbus, and the nonce cookie set along the way.
+    async fn start_login(app: &Router) -> (String, String) {
+        let response = request(app, "/login?return_to=/polls", None).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+        let cookie = response.headers()[SET_COOKIE]
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_owned();
+
+        let provider_response = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .get(location(&response))
+            .send()
+            .await
+            .unwrap();
+
+        let callback = provider_response.headers()[LOCATION].to_str().unwrap();
+
+        (
+            callback
+                .strip_prefix(MOTHBUS_URL)
+                .expect("provider didn't send the player back to mothbus")
+                .to_owned(),
+            cookie,
+        )
+    }
+
+    #[tokio::test]
+    async fn login_creates_session() {
+        let fake = FakeOAuthServer::start(FakeAccount::User(serde_json::json!({
+            "byond_ckey": "mothblocks",
+        })))
+        .await;
+
+        let (app, state) = test_app(&fake, None);
+        let (callback, cookie) = start_login(&app).await;
+
+        let response = request(&app, &callback, Some(&cookie)).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(location(&response), "/polls");
+
+        let session_jwt = response
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|cookie| cookie.to_str().unwrap().strip_prefix("session_jwt="))
+            .map(|cookie| cookie.split(';').next().unwrap().to_owned())
+            .next()
+            .expect("no session cookie set");
+
+        let session = Arc::clone(&state)
+            .session(&session_jwt)
+            .await
+            .unwrap()
+            .expect("session wasn't stored");
+        assert_eq!(session.ckey, "mothblocks");
+
+        let sessions = state.sessions.sessions_for("mothblocks").await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, session.sid);
+        assert_eq!(sessions[0].ip, "127.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn login_without_ckey() {
+        let fake = FakeOAuthServer::start(FakeAccount::User(serde_json::json!({
+            "byond_ckey": null,
+        })))
+        .await;
+
+        let (app, _) = test_app(&fake, None);
+        let (callback, cookie) = start_login(&app).await;
+
+        let response = request(&app, &callback, Some(&cookie)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            body_text(response).await,
+            "401: your forum account has no ckey linked"
+        );
+
+        // The state has been used up, so it's rejected before reaching the provider
+        let response = request(&app, &callback, Some(&cookie)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            body_text(response).await,
+            "401: this login has already been used"
+        );
+    }
+
+    #[tokio::test]
+    async fn login_from_another_browser() {
+        let fake = FakeOAuthServer::start(FakeAccount::User(serde_json::json!({
+            "byond_ckey": "mothblocks",
+        })))
+        .await;
+
+        let (app, _) = test_app(&fake, None);
+        let (callback, _) = start_login(&app).await;
+
+        for cookie in [None, Some("oauth_nonce=someone-elses")] {
+            let response = request(&app, &callback, cookie).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(
+                body_text(response).await,
+                "401: login expired or was started somewhere else, try logging in again"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn login_denied() {
+        let fake = FakeOAuthServer::start(FakeAccount::Denied).await;
+
+        let (app, _) = test_app(&fake, None);
+        let (callback, cookie) = start_login(&app).await;
+
+        let response = request(&app, &callback, Some(&cookie)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            body_text(response).await,
+            "401: The player declined (access_denied)"
+        );
+    }
+
+    #[tokio::test]
+    async fn login_with_wrong_client_secret() {
+        let fake = FakeOAuthServer::start(FakeAccount::User(serde_json::json!({
+            "byond_ckey": "mothblocks",
+        })))
+        .await;
+
+        let (app, _) = test_app(&fake, Some("hunter3"));
+        let (callback, cookie) = start_login(&app).await;
+
+        let response = request(&app, &callback, Some(&cookie)).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(body_text(response).await.contains("invalid_client"));
+    }
+}
@@ -1,13 +1,27 @@
-use axum::response::{AppendHeaders, IntoResponse, Redirect};
-use http::header::SET_COOKIE;
-
-#[tracing::instrument]
-pub async fn logout() -> impl IntoResponse {
-    (
-        AppendHeaders([(
-            SET_COOKIE,
-            "session_jwt=; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Path=/;",
-        )]),
-        Redirect::to("/"),
-    )
+use std::sync::Arc;
+
+use axum::{
+    response::{IntoResponse, Redirect},
+    Extension,
+};
+use axum_extra::extract::cookie::CookieJar;
+
+use crate::{auth::CurrentSession, cookies, csrf::CsrfProtected, State};
+
+// A POST with a CSRF token, so that other sites can't log players out
+#[tracing::instrument(skip(cookie_jar))]
+pub async fn logout(
+    Extension(state): Extension<Arc<State>>,
+    CurrentSession(session): CurrentSession,
+    cookie_jar: CookieJar,
+    _csrf: CsrfProtected,
+) -> impl IntoResponse {
+    // Revoked so that the token can't be reused if it was copied somewhere
+    if let Some(session) = session {
+        if let Err(error) = state.revoke_session(&session.ckey, &session.sid).await {
+            tracing::error!("failed to revoke session on logout: {error:#}");
+        }
+    }
+
+    (cookies::remove_session(cookie_jar), Redirect::to("/"))
 }
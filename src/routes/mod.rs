@@ -2,12 +2,22 @@ use std::borrow::Cow;
 
 use serde::Serialize;
 
+pub mod access_log;
+
+pub mod api_tokens;
+
+pub mod discord_deliveries;
+
 pub mod errors;
 pub use errors::not_found;
 
+pub mod github_deliveries;
+
 pub mod github_webhook;
 pub use github_webhook::github_webhook;
 
+pub mod hooks;
+
 mod index;
 pub use index::index;
 
@@ -27,6 +37,8 @@ pub use round_info::round_info;
 
 pub mod polls;
 
+pub mod sessions;
+
 pub mod tickets;
 
 pub mod user;
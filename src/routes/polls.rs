@@ -4,12 +4,22 @@ use std::{
     time::Duration,
 };
 
-use axum::{extract::Path, response::IntoResponse, Extension};
+use axum::{
+    extract::Path,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use http::StatusCode;
 use moka::future::Cache;
 use serde::Serialize;
 use sqlx::{mysql::MySqlRow, Row};
 
-use crate::{auth::AuthenticatedUserOptional, state::User, State};
+use crate::{
+    access_log::{self, AccessKind},
+    auth::AuthenticatedUserOptional,
+    state::User,
+    State,
+};
 
 use super::TemplateBase;
 
@@ -258,12 +268,184 @@ pub async fn create_poll_cache(state: Arc<State>) -> color_eyre::Result<HashMap<
     Ok(polls)
 }
 
+/// What `/polls.json` and `/polls/:poll.json` show of a poll.
+#[derive(Debug, Serialize)]
+pub struct PollResults {
+    id: i32,
+
+    question: String,
+    subtitle: Option<String>,
+
+    admin_only: bool,
+    created_by_ckey: String,
+    start_date: String,
+    seconds_until_end: i64,
+
+    /// Votes across every option. Players can pick more than one option in multiple choice
+    /// and rating polls, so this can be more than how many players voted.
+    total_votes: i64,
+
+    #[serde(flatten)]
+    results: Results,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Results {
+    Choice {
+        options: Vec<ChoiceResult>,
+    },
+
+    NumVal {
+        options: Vec<NumValResult>,
+    },
+
+    Text {
+        /// Left out of the list of every poll, as there can be thousands.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        replies: Option<Vec<TextReply>>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct ChoiceResult {
+    text: String,
+    votes: i64,
+    percentage: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct NumValResult {
+    text: String,
+    votes: i64,
+    ratings: Vec<RatingResult>,
+}
+
+#[derive(Debug, Serialize)]
+struct RatingResult {
+    rating: i32,
+    votes: i64,
+    /// Out of the votes for this option, rather than the whole poll.
+    percentage: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct TextReply {
+    /// Only given to those who can read text poll ckeys.
+    ckey: Option<String>,
+    text: String,
+}
+
+impl PollResults {
+    fn new(poll: &Poll, can_read_text_ckeys: bool) -> Self {
+        let (total_votes, results) = match &poll.options {
+            PollOptions::Choice(options) => {
+                let total_votes = options.iter().map(|(_, votes)| votes).sum();
+
+                let options = options
+                    .iter()
+                    .map(|(text, votes)| ChoiceResult {
+                        text: text.clone(),
+                        votes: *votes,
+                        percentage: percentage(*votes, total_votes),
+                    })
+                    .collect();
+
+                (total_votes, Results::Choice { options })
+            }
+
+            PollOptions::NumVal(options) => {
+                let options: Vec<NumValResult> = options
+                    .iter()
+                    .map(|(text, ratings)| {
+                        let votes = ratings.iter().map(|&(_, votes)| i64::from(votes)).sum();
+
+                        NumValResult {
+                            text: text.clone(),
+                            votes,
+                            ratings: ratings
+                                .iter()
+                                .map(|&(rating, rating_votes)| RatingResult {
+                                    rating,
+                                    votes: rating_votes.into(),
+                                    percentage: percentage(rating_votes.into(), votes),
+                                })
+                                .collect(),
+                        }
+                    })
+                    .collect();
+
+                (
+                    options.iter().map(|option| option.votes).sum(),
+                    Results::NumVal { options },
+                )
+            }
+
+            PollOptions::Text(answers) => (
+                answers.len() as i64,
+                Results::Text {
+                    replies: Some(
+                        answers
+                            .iter()
+                            .map(|answer| TextReply {
+                                ckey: can_read_text_ckeys.then(|| answer.ckey.clone()),
+                                text: answer.text.clone(),
+                            })
+                            .collect(),
+                    ),
+                },
+            ),
+        };
+
+        Self {
+            id: poll.id,
+
+            question: poll.question.clone(),
+            subtitle: poll.subtitle.clone(),
+
+            admin_only: poll.admin_only,
+            created_by_ckey: poll.created_by_ckey.clone(),
+            start_date: poll.start_date.clone(),
+            seconds_until_end: poll.seconds_until_end,
+
+            total_votes,
+            results,
+        }
+    }
+
+    fn without_replies(mut self) -> Self {
+        if let Results::Text { replies } = &mut self.results {
+            *replies = None;
+        }
+
+        self
+    }
+}
+
+// Rounded to two decimal places
+fn percentage(votes: i64, total_votes: i64) -> f64 {
+    if total_votes == 0 {
+        return 0.0;
+    }
+
+    (votes as f64 / total_votes as f64 * 10_000.0).round() / 100.0
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 enum PollAccessDenyReason {
     AdminOnly,
     NotFinished,
 }
 
+impl PollAccessDenyReason {
+    fn message(self) -> &'static str {
+        match self {
+            PollAccessDenyReason::AdminOnly => "you must be an admin",
+            PollAccessDenyReason::NotFinished => "poll has not finished yet",
+        }
+    }
+}
+
 fn try_access_poll(user: Option<&User>, poll: &Poll) -> Result<(), PollAccessDenyReason> {
     let user_can_read_admin_polls = match &user {
         Some(user) => user.can_read_admin_only_polls(),
@@ -281,6 +463,31 @@ fn try_access_poll(user: Option<&User>, poll: &Poll) -> Result<(), PollAccessDen
     Ok(())
 }
 
+/// Records the privileged parts of a poll the user is being shown.
+async fn record_poll_view(state: &State, user: Option<&User>, poll: &Poll) {
+    let Some(user) = user else {
+        return;
+    };
+
+    if poll.admin_only {
+        access_log::record(state, user, AccessKind::AdminOnlyPoll, &poll.id.to_string()).await;
+    }
+
+    if user.can_read_text_ckeys() && matches!(poll.options, PollOptions::Text(_)) {
+        access_log::record(state, user, AccessKind::TextPollCkeys, &poll.id.to_string()).await;
+    }
+}
+
+fn json_error(status_code: StatusCode, message: &str) -> Response {
+    (
+        status_code,
+        Json(serde_json::json!({
+            "error": message,
+        })),
+    )
+        .into_response()
+}
+
 #[derive(Serialize)]
 struct PollsTemplate {
     base: TemplateBase,
@@ -288,14 +495,29 @@ struct PollsTemplate {
     can_read_admin_only_polls: bool,
 }
 
+async fn accessible_polls(state: Arc<State>, user: Option<&User>) -> color_eyre::Result<Vec<Poll>> {
+    let polls = state.poll_cache.get(Arc::clone(&state)).await?;
+
+    tracing::trace!("number of polls = {}", polls.len());
+    tracing::trace!("first poll: {:#?}", polls.values().next());
+
+    let mut polls = polls
+        .values()
+        .filter(|poll| try_access_poll(user, poll).is_ok())
+        .cloned()
+        .collect::<Vec<_>>();
+
+    polls.sort_by_key(|poll| -poll.id);
+
+    Ok(polls)
+}
+
 #[tracing::instrument]
 pub async fn index(
     Extension(state): Extension<Arc<State>>,
     AuthenticatedUserOptional(user): AuthenticatedUserOptional,
 ) -> impl IntoResponse {
-    let cache = &state.poll_cache;
-
-    let polls = match cache.get(Arc::clone(&state)).await {
+    let polls = match accessible_polls(Arc::clone(&state), user.as_ref()).await {
         Ok(polls) => polls,
         Err(error) => {
             return super::errors::make_internal_server_error(state, error)
@@ -304,15 +526,6 @@ pub async fn index(
         }
     };
 
-    tracing::trace!("number of polls = {}", polls.len());
-    tracing::trace!("first poll: {:#?}", polls.values().next());
-
-    let mut polls = polls.values().cloned().collect::<Vec<_>>();
-
-    polls.retain(|poll| try_access_poll(user.as_ref(), poll).is_ok());
-
-    polls.sort_by_key(|poll| -poll.id);
-
     state.render_template(
         "polls",
         PollsTemplate {
@@ -331,6 +544,36 @@ pub async fn index(
     )
 }
 
+/// Every poll the user can see, with results. Text replies are only given for single polls.
+#[tracing::instrument]
+pub async fn index_json(
+    Extension(state): Extension<Arc<State>>,
+    AuthenticatedUserOptional(user): AuthenticatedUserOptional,
+) -> Response {
+    let polls = match accessible_polls(Arc::clone(&state), user.as_ref()).await {
+        Ok(polls) => polls,
+        Err(error) => {
+            tracing::error!("error getting polls: {error:#?}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "error getting polls");
+        }
+    };
+
+    // One row for the whole list, and only when it had results the public can't see
+    if let Some(user) = &user {
+        if polls.iter().any(|poll| poll.admin_only) {
+            access_log::record(&state, user, AccessKind::AdminOnlyPoll, "*").await;
+        }
+    }
+
+    Json(
+        polls
+            .iter()
+            .map(|poll| PollResults::new(poll, false).without_replies())
+            .collect::<Vec<_>>(),
+    )
+    .into_response()
+}
+
 #[derive(Serialize)]
 struct PollTemplate {
     base: TemplateBase,
@@ -338,16 +581,24 @@ struct PollTemplate {
     can_read_text_ckeys: bool,
 }
 
+/// Serves both `/polls/:poll` and `/polls/:poll.json`, which the router can't tell apart.
 #[tracing::instrument]
 pub async fn for_poll(
     Extension(state): Extension<Arc<State>>,
     AuthenticatedUserOptional(user): AuthenticatedUserOptional,
-    Path(id): Path<i32>,
-) -> impl IntoResponse {
-    let cache = &state.poll_cache;
+    Path(poll): Path<String>,
+) -> Response {
+    let (id, json) = match poll.strip_suffix(".json") {
+        Some(id) => (id, true),
+        None => (poll.as_str(), false),
+    };
 
-    let polls = match cache.get(Arc::clone(&state)).await {
+    let polls = match state.poll_cache.get(Arc::clone(&state)).await {
         Ok(polls) => polls,
+        Err(error) if json => {
+            tracing::error!("error getting polls: {error:#?}");
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "error getting polls");
+        }
         Err(error) => {
             return super::errors::make_internal_server_error(state, error)
                 .await
@@ -355,8 +606,9 @@ pub async fn for_poll(
         }
     };
 
-    let poll = match polls.get(&id) {
+    let poll = match id.parse().ok().and_then(|id: i32| polls.get(&id)) {
         Some(poll) => poll,
+        None if json => return json_error(StatusCode::NOT_FOUND, "poll not found"),
         None => {
             return super::errors::make_not_found(state, "poll not found")
                 .await
@@ -365,25 +617,31 @@ pub async fn for_poll(
     };
 
     if let Err(problem) = try_access_poll(user.as_ref(), poll) {
-        return super::errors::make_forbidden(
-            state,
-            match problem {
-                PollAccessDenyReason::AdminOnly => "you must be an admin",
-                PollAccessDenyReason::NotFinished => "poll has not finished yet",
-            },
-        )
-        .await
-        .into_response();
+        if json {
+            return json_error(StatusCode::FORBIDDEN, problem.message());
+        }
+
+        return super::errors::make_forbidden(state, problem.message())
+            .await
+            .into_response();
+    }
+
+    let can_read_text_ckeys = match &user {
+        Some(user) => user.can_read_text_ckeys(),
+        None => false,
+    };
+
+    record_poll_view(&state, user.as_ref(), poll).await;
+
+    if json {
+        return Json(PollResults::new(poll, can_read_text_ckeys)).into_response();
     }
 
     state.render_template(
         "poll",
         PollTemplate {
             poll: poll.clone(),
-            can_read_text_ckeys: match &user {
-                Some(user) => user.can_read_text_ckeys(),
-                None => false,
-            },
+            can_read_text_ckeys,
 
             base: TemplateBase {
                 title: poll.question.clone().into(),
@@ -430,3 +688,91 @@ impl Default for PollCache {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poll(options: PollOptions) -> Poll {
+        Poll {
+            id: 1,
+
+            question: "Moths?".to_owned(),
+            subtitle: None,
+            options,
+
+            admin_only: false,
+            created_by_ckey: "mothblocks".to_owned(),
+            start_date: "2023-01-01".to_owned(),
+            wait_for_results: false,
+
+            seconds_until_end: -1,
+        }
+    }
+
+    #[test]
+    fn choice_results() {
+        let results = serde_json::to_value(PollResults::new(
+            &poll(PollOptions::Choice(vec![
+                ("Yes".to_owned(), 3),
+                ("No".to_owned(), 1),
+                ("Maybe".to_owned(), 0),
+            ])),
+            false,
+        ))
+        .unwrap();
+
+        assert_eq!(results["type"], "choice");
+        assert_eq!(results["total_votes"], 4);
+        assert_eq!(
+            results["options"],
+            serde_json::json!([
+                { "text": "Yes", "votes": 3, "percentage": 75.0 },
+                { "text": "No", "votes": 1, "percentage": 25.0 },
+                { "text": "Maybe", "votes": 0, "percentage": 0.0 },
+            ])
+        );
+    }
+
+    #[test]
+    fn numval_percentages_are_per_option() {
+        let results = serde_json::to_value(PollResults::new(
+            &poll(PollOptions::NumVal(vec![
+                ("Lighting".to_owned(), vec![(1, 1), (2, 2)]),
+                ("Sound".to_owned(), vec![(1, 0), (2, 0)]),
+            ])),
+            false,
+        ))
+        .unwrap();
+
+        assert_eq!(results["type"], "numval");
+        assert_eq!(results["total_votes"], 3);
+        assert_eq!(results["options"][0]["votes"], 3);
+        assert_eq!(results["options"][0]["ratings"][0]["percentage"], 33.33);
+        assert_eq!(results["options"][0]["ratings"][1]["percentage"], 66.67);
+        assert_eq!(results["options"][1]["ratings"][0]["percentage"], 0.0);
+    }
+
+    #[test]
+    fn text_ckeys_only_for_those_who_can_read_them() {
+        let text_poll = poll(PollOptions::Text(vec![TextAnswer {
+            ckey: "mothblocks".to_owned(),
+            text: "more moths".to_owned(),
+        }]));
+
+        let anonymous = serde_json::to_value(PollResults::new(&text_poll, false)).unwrap();
+        assert_eq!(anonymous["total_votes"], 1);
+        assert_eq!(
+            anonymous["replies"],
+            serde_json::json!([{ "ckey": null, "text": "more moths" }])
+        );
+
+        let with_ckeys = serde_json::to_value(PollResults::new(&text_poll, true)).unwrap();
+        assert_eq!(with_ckeys["replies"][0]["ckey"], "mothblocks");
+
+        let listed =
+            serde_json::to_value(PollResults::new(&text_poll, true).without_replies()).unwrap();
+        assert_eq!(listed["total_votes"], 1);
+        assert!(listed.get("replies").is_none());
+    }
+}
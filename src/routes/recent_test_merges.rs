@@ -1,119 +1,246 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, BTreeSet, HashMap},
     sync::Arc,
-    time::{Duration, Instant},
+    time::Duration,
 };
 
-use axum::{response::IntoResponse, Extension, Json};
+use axum::{extract::Query, response::IntoResponse, Extension, Json};
+use color_eyre::eyre::Context;
 use http::StatusCode;
+use moka::future::Cache;
 use once_cell::sync::Lazy;
-use serde::Serialize;
-use sqlx::Row;
-use tokio::sync::RwLock;
+use serde::{Deserialize, Serialize};
+use sqlx::{mysql::MySqlRow, Row};
+
+use crate::urls::UrlTemplates;
+
+const DEFAULT_LIMIT: u32 = 200;
+const MAX_LIMIT: u32 = 1000;
 
 #[derive(Clone, Serialize)]
 pub struct TestMerge {
     pub round_id: u64,
     pub datetime: chrono::NaiveDateTime,
     pub test_merges: Vec<u64>,
+    pub pull_requests: Vec<TestMergedPullRequest>,
     pub server: String,
     pub url: String,
 }
 
-type TestMerges = Vec<TestMerge>;
-type RecentTestMergesCache = Option<(Instant, TestMerges)>;
+/// Titles and authors come from the round's feedback, and are replaced by the
+/// pull request cache (along with labels and state) when it knows about the pull request.
+#[derive(Clone, Serialize)]
+pub struct TestMergedPullRequest {
+    pub number: u64,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub labels: Vec<String>,
+    pub state: Option<String>,
+    pub url: String,
+}
 
-static LAST_RECENT_TEST_MERGES: Lazy<Arc<RwLock<RecentTestMergesCache>>> =
-    Lazy::new(|| Arc::new(RwLock::new(None)));
+#[derive(Deserialize)]
+struct FeedbackTestMerge {
+    number: serde_json::Value,
+    title: Option<String>,
+    author: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq)]
+pub struct RecentTestMergesQuery {
+    server: Option<String>,
+    pr: Option<u64>,
+    since: Option<chrono::NaiveDate>,
+    limit: Option<u32>,
+}
+
+type TestMerges = Arc<Vec<TestMerge>>;
+
+static RECENT_TEST_MERGES_CACHE: Lazy<Cache<RecentTestMergesQuery, TestMerges>> = Lazy::new(|| {
+    Cache::builder()
+        .max_capacity(100)
+        .time_to_live(Duration::from_secs(60 * 10))
+        .build()
+});
 
 #[tracing::instrument]
 pub async fn recent_test_merges(
     Extension(state): Extension<Arc<crate::State>>,
+    Query(mut query): Query<RecentTestMergesQuery>,
 ) -> impl IntoResponse {
-    {
-        let last_recent_test_merges = LAST_RECENT_TEST_MERGES.read().await;
-        if let Some((last_update, test_merges)) = last_recent_test_merges.as_ref() {
-            if last_update.elapsed() < Duration::from_secs(60 * 10) {
-                return Json(test_merges.clone()).into_response();
+    let server_port = match &query.server {
+        Some(server_name) => match crate::servers::server_by_name(server_name) {
+            Some(server) => Some(server.port),
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!("\"{server_name}\" is not a valid server"),
+                )
+                    .into_response();
             }
-        }
-    }
+        },
 
-    let mut last_recent_test_merges = LAST_RECENT_TEST_MERGES.write().await;
+        None => None,
+    };
 
-    tracing::debug!("requesting recent test merges from db");
+    // Normalize the limit so that the cache isn't split over equivalent queries
+    query.limit = Some(query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT));
+
+    // Concurrent misses for the same query wait on a single fetch, rather than each running it
+    let test_merges = RECENT_TEST_MERGES_CACHE
+        .try_get_with_by_ref(&query, async {
+            tracing::debug!("requesting recent test merges from db");
+
+            fetch_test_merges(&state, &query, server_port)
+                .await
+                .map(Arc::new)
+        })
+        .await;
+
+    match test_merges {
+        Ok(test_merges) => Json(test_merges.as_slice()).into_response(),
+
+        Err(error) => {
+            tracing::error!("failed to fetch new test merges: {error:#?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "something went wrong, contact mothblocks",
+            )
+                .into_response()
+        }
+    }
+}
 
-    match sqlx::query(
+async fn fetch_test_merges(
+    state: &crate::State,
+    query: &RecentTestMergesQuery,
+    server_port: Option<u16>,
+) -> color_eyre::Result<Vec<TestMerge>> {
+    let rows = sqlx::query(&format!(
         "SELECT
             round_id,
             datetime,
-            JSON_EXTRACT(json, '$.data.*.number') AS test_merges,
+            JSON_EXTRACT(json, '$.data.*') AS test_merges,
             round.server_port
         FROM
-            tgstation13.feedback
+            {}.feedback
         JOIN round ON round.id = round_id
         WHERE
             key_name = 'testmerged_prs'
+            AND (? IS NULL OR round.server_port = ?)
+            AND (? IS NULL OR JSON_CONTAINS(JSON_EXTRACT(json, '$.data.*.number'), JSON_QUOTE(?)))
+            AND (? IS NULL OR datetime >= ?)
         ORDER BY round.id DESC
-        LIMIT 200;
+        LIMIT ?;
     ",
-    )
+        state.config.db_schema,
+    ))
+    .bind(server_port)
+    .bind(server_port)
+    .bind(query.pr.map(|pr| pr.to_string()))
+    .bind(query.pr.map(|pr| pr.to_string()))
+    .bind(query.since)
+    .bind(query.since)
+    .bind(query.limit.unwrap_or(DEFAULT_LIMIT))
     .fetch_all(&state.mysql_pool)
     .await
+    .context("failed to fetch test merges")?;
+
+    let mut test_merges = rows
+        .into_iter()
+        .filter_map(|row| match test_merge_from_row(&row, &state.config.urls) {
+            Ok(test_merge) => Some(test_merge),
+            Err(error) => {
+                tracing::warn!("skipping malformed testmerged_prs row: {error:#}");
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let numbers = test_merges
+        .iter()
+        .flat_map(|test_merge| test_merge.test_merges.iter().copied())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    // The feedback already has titles and authors, so a missing cache isn't fatal
+    let cached_pull_requests = match crate::pull_requests::pull_requests_by_number(
+        &state.mysql_pool,
+        &state.config.github_webhook.test_merge_repository,
+        &numbers,
+    )
+    .await
     {
-        Ok(rows) => {
-            let output = rows.into_iter()
-                .map(|row| {
-                    let datetime: chrono::NaiveDateTime = row.get::<_, &'static str>("datetime");
-
-                    let test_merges: Vec<u64> = serde_json::from_str::<HashSet<String>>(
-                        &row.get::<String, &'static str>("test_merges"),
-                    )
-                    .expect("test_merges is not a valid json array")
-                    .into_iter()
-                    .map(|test_merge_id| {
-                        test_merge_id.parse().unwrap_or_else(|_| {
-                            panic!("{test_merge_id} is not a valid test_merge_id")
-                        })
-                    })
-                    .collect();
-
-                    let port = row.get("server_port");
-                    let round_id = row.get("round_id");
-
-                    let server_name = crate::servers::server_by_port(port)
-                        .map(|server| server.name.to_owned())
-                        .unwrap_or_else(|| format!("Unknown ({port})"));
-
-                    TestMerge {
-                        round_id,
-                        datetime,
-                        test_merges,
-                        server: server_name.clone(),
-                        url: format!(
-                            "https://tgstation13.org/parsed-logs/{server_name}/data/logs/{}/{}/{}/round-{round_id}/",
-                            datetime.format("%Y"),
-                            datetime.format("%m"),
-                            datetime.format("%d"),
-                        ),
-                    }
-                })
-                .collect::<Vec<_>>();
-
-            tracing::debug!("Updating recent test merges");
-
-            *last_recent_test_merges = Some((Instant::now(), output.clone()));
-
-            Json(output).into_response()
+        Ok(pull_requests) => pull_requests,
+        Err(error) => {
+            tracing::error!("failed to fetch cached pull requests: {error:#}");
+            HashMap::new()
         }
+    };
+
+    for test_merge in &mut test_merges {
+        for pull_request in &mut test_merge.pull_requests {
+            if let Some(cached) = cached_pull_requests.get(&pull_request.number) {
+                pull_request.title = Some(cached.title.clone());
+                pull_request.author = Some(cached.author.clone());
+                pull_request.labels = cached.labels.clone();
+                pull_request.state = Some(cached.state.clone());
+            }
+        }
+    }
 
-        Err(error) => {
-            tracing::error!("failed to fetch new test merges: {error:#?}");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "something went wrong, contact mothblocks",
-            )
-                .into_response()
+    Ok(test_merges)
+}
+
+fn test_merge_from_row(row: &MySqlRow, urls: &UrlTemplates) -> color_eyre::Result<TestMerge> {
+    let round_id: u64 = row.try_get("round_id")?;
+    let datetime: chrono::NaiveDateTime = row.try_get("datetime")?;
+    let port: u16 = row.try_get("server_port")?;
+
+    let feedback_entries = serde_json::from_str::<Vec<FeedbackTestMerge>>(
+        &row.try_get::<String, _>("test_merges")?,
+    )
+    .with_context(|| format!("test_merges for round {round_id} is not a valid json array"))?;
+
+    let mut pull_requests: BTreeMap<u64, TestMergedPullRequest> = BTreeMap::new();
+
+    for entry in feedback_entries {
+        let number = match &entry.number {
+            serde_json::Value::Number(number) => number.as_u64(),
+            serde_json::Value::String(number) => number.parse().ok(),
+            _ => None,
         }
+        .ok_or_else(|| {
+            color_eyre::eyre::eyre!(
+                "{} in round {round_id} is not a valid test_merge_id",
+                entry.number
+            )
+        })?;
+
+        pull_requests.insert(
+            number,
+            TestMergedPullRequest {
+                number,
+                title: entry.title,
+                author: entry.author,
+                labels: Vec::new(),
+                state: None,
+                url: urls.github_pull_request(number),
+            },
+        );
     }
+
+    let server_name = crate::servers::server_by_port(port)
+        .map(|server| server.name.to_owned())
+        .unwrap_or_else(|| format!("Unknown ({port})"));
+
+    Ok(TestMerge {
+        round_id,
+        datetime,
+        test_merges: pull_requests.keys().copied().collect(),
+        pull_requests: pull_requests.into_values().collect(),
+        url: urls.round_logs(&server_name, round_id, datetime),
+        server: server_name,
+    })
 }
@@ -21,6 +21,7 @@ pub enum RoundInfoResult {
         #[serde(flatten)]
         round_info: RoundInfo,
         server: Option<&'static str>,
+        logs_url: Option<String>,
     },
 }
 
@@ -38,11 +39,12 @@ pub async fn round_info(
     Query(params): Query<RoundInfoQuery>,
 ) -> impl IntoResponse {
     let Some(round_id) = params.round_id else {
-		return Json(RoundInfoResult::RoundNotFound {
-			success: false,
-			error: "you must specify a round id by passing a `round_id` parameter".to_string(),
-		}).into_response();
-	};
+        return Json(RoundInfoResult::RoundNotFound {
+            success: false,
+            error: "you must specify a round id by passing a `round_id` parameter".to_string(),
+        })
+        .into_response();
+    };
 
     let round_info = match sqlx::query_as::<_, RoundInfo>(
         "SELECT id, initialize_datetime, end_datetime, server_port FROM round WHERE id = ?",
@@ -81,10 +83,19 @@ pub async fn round_info(
 
     let server = crate::servers::server_by_port(round_info.server_port);
 
+    let logs_url = server.map(|server| {
+        state.config.urls.round_logs(
+            server.name,
+            round_info.id as u64,
+            round_info.initialize_datetime,
+        )
+    });
+
     Json(RoundInfoResult::RoundInfo {
         success: true,
         round_info,
         server: server.map(|server| server.name),
+        logs_url,
     })
     .into_response()
 }
@@ -0,0 +1,218 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query},
+    response::{IntoResponse, Redirect},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::{AuthenticatedUser, CurrentSession},
+    csrf::{CsrfForm, CsrfProtected},
+    session_store::StoredSession,
+    State,
+};
+
+use super::{
+    errors::{make_bad_request, make_forbidden, make_internal_server_error, make_not_found},
+    TemplateBase,
+};
+
+const FORBIDDEN: &str = "You do not have permission to manage other people's sessions.";
+
+#[derive(Serialize)]
+struct SessionsTemplate {
+    base: TemplateBase,
+    sessions: Vec<SessionView>,
+}
+
+#[derive(Serialize)]
+struct SessionView {
+    #[serde(flatten)]
+    session: StoredSession,
+    current: bool,
+}
+
+#[tracing::instrument]
+pub async fn index(
+    Extension(state): Extension<Arc<State>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    CurrentSession(current_session): CurrentSession,
+) -> impl IntoResponse {
+    let sessions = match state.sessions.sessions_for(&user.ckey).await {
+        Ok(sessions) => sessions,
+        Err(error) => {
+            return make_internal_server_error(state, error)
+                .await
+                .into_response();
+        }
+    };
+
+    let current_sid = current_session.map(|session| session.sid);
+
+    state.render_template(
+        "sessions",
+        SessionsTemplate {
+            base: TemplateBase {
+                title: "sessions".into(),
+                user: Some(user),
+            },
+
+            sessions: sessions
+                .into_iter()
+                .map(|session| SessionView {
+                    current: current_sid.as_ref() == Some(&session.id),
+                    session,
+                })
+                .collect(),
+        },
+    )
+}
+
+#[tracing::instrument]
+pub async fn revoke(
+    Path(id): Path<String>,
+    Extension(state): Extension<Arc<State>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    _csrf: CsrfProtected,
+) -> impl IntoResponse {
+    match state.revoke_session(&user.ckey, &id).await {
+        Ok(true) => Redirect::to("/sessions").into_response(),
+
+        Ok(false) => make_not_found(state, "session not found")
+            .await
+            .into_response(),
+
+        Err(error) => make_internal_server_error(state, error)
+            .await
+            .into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminSessionsParams {
+    ckey: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AdminSessionsTemplate {
+    base: TemplateBase,
+    ckey: Option<String>,
+    sessions: Vec<StoredSession>,
+    revoked: Option<u64>,
+}
+
+#[tracing::instrument]
+pub async fn admin_index(
+    Query(params): Query<AdminSessionsParams>,
+    Extension(state): Extension<Arc<State>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+) -> impl IntoResponse {
+    if !user.can_manage_sessions() {
+        return make_forbidden(state, FORBIDDEN).await.into_response();
+    }
+
+    render_admin_index(state, user, params.ckey, None)
+        .await
+        .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeAllForm {
+    ckey: String,
+}
+
+#[tracing::instrument]
+pub async fn admin_revoke_all(
+    Extension(state): Extension<Arc<State>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    CsrfForm(form): CsrfForm<RevokeAllForm>,
+) -> impl IntoResponse {
+    if !user.can_manage_sessions() {
+        return make_forbidden(state, FORBIDDEN).await.into_response();
+    }
+
+    let ckey = canonical_ckey(&form.ckey);
+    if ckey.is_empty() {
+        return make_bad_request(state, &format!("{:?} is not a ckey", form.ckey))
+            .await
+            .into_response();
+    }
+
+    let revoked = match state.revoke_all_sessions_for(&ckey).await {
+        Ok(revoked) => revoked,
+        Err(error) => {
+            return make_internal_server_error(state, error)
+                .await
+                .into_response();
+        }
+    };
+
+    tracing::info!("{} revoked {revoked} sessions for {ckey}", user.ckey);
+
+    render_admin_index(state, user, Some(ckey), Some(revoked))
+        .await
+        .into_response()
+}
+
+async fn render_admin_index(
+    state: Arc<State>,
+    user: crate::state::User,
+    ckey: Option<String>,
+    revoked: Option<u64>,
+) -> impl IntoResponse {
+    let ckey = ckey
+        .map(|ckey| canonical_ckey(&ckey))
+        .filter(|ckey| !ckey.is_empty());
+
+    let sessions = match &ckey {
+        Some(ckey) => match state.sessions.sessions_for(ckey).await {
+            Ok(sessions) => sessions,
+            Err(error) => {
+                return make_internal_server_error(state, error)
+                    .await
+                    .into_response();
+            }
+        },
+
+        None => Vec::new(),
+    };
+
+    state.render_template(
+        "admin_sessions",
+        AdminSessionsTemplate {
+            base: TemplateBase {
+                title: "manage sessions".into(),
+                user: Some(user),
+            },
+
+            ckey,
+            sessions,
+            revoked,
+        },
+    )
+}
+
+/// Keys are typed as people write them, such as "Moth Blocks", but stored as the game's `ckey()`.
+fn canonical_ckey(key: &str) -> String {
+    key.chars()
+        .map(|c| c.to_ascii_lowercase())
+        .filter(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '@')
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ckeys_are_canonical() {
+        assert_eq!(canonical_ckey("Moth Blocks"), "mothblocks");
+        assert_eq!(canonical_ckey(" mothblocks "), "mothblocks");
+        assert_eq!(canonical_ckey("Guest-1234"), "guest1234");
+        assert_eq!(canonical_ckey("@Moth_Blocks"), "@mothblocks");
+        assert_eq!(canonical_ckey("Möth"), "mth");
+        assert_eq!(canonical_ckey("  "), "");
+    }
+}
@@ -9,7 +9,13 @@ use color_eyre::eyre::Context;
 use http::StatusCode;
 use serde::{Deserialize, Serialize, Serializer};
 
-use crate::{auth::AuthenticatedUser, servers::Servers, state::User, State};
+use crate::{
+    access_log::{self, AccessKind},
+    auth::AuthenticatedUser,
+    servers::Servers,
+    state::User,
+    State,
+};
 
 use super::{
     errors::{make_forbidden, make_not_found},
@@ -138,6 +144,16 @@ struct TicketsListTemplate {
     who: String,
     page: u32,
     tickets: Vec<WithColor<Ticket>>,
+    /// Only for a single round's tickets, to link to its logs.
+    round: Option<RoundLogs>,
+}
+
+/// What `round_logs_url` needs to find a round's logs.
+#[derive(Serialize)]
+struct RoundLogs {
+    round_id: u64,
+    server: &'static str,
+    datetime: chrono::NaiveDateTime,
 }
 
 #[derive(Serialize)]
@@ -214,6 +230,10 @@ pub async fn for_ckey(
         }
     };
 
+    if user.ckey != ckey {
+        access_log::record(&state, &user, AccessKind::PlayerTickets, &ckey).await;
+    }
+
     render_tickets(
         state,
         params,
@@ -226,6 +246,7 @@ pub async fn for_ckey(
             who: ckey,
             page,
             tickets,
+            round: None,
         },
     )
     .into_response()
@@ -296,6 +317,8 @@ pub async fn for_server(
         }
     };
 
+    access_log::record(&state, &user, AccessKind::ServerTickets, &server_name).await;
+
     render_tickets(
         state,
         params,
@@ -307,6 +330,7 @@ pub async fn for_server(
             who: server_name,
             page,
             tickets,
+            round: None,
         },
     )
     .into_response()
@@ -368,6 +392,38 @@ pub async fn for_round(
         }
     };
 
+    // Rounds on servers we don't know the logs for get no link
+    let round = match sqlx::query_as::<_, (chrono::NaiveDateTime, u16)>(
+        "SELECT initialize_datetime, server_port FROM round WHERE id = ?",
+    )
+    .bind(round_id)
+    .fetch_optional(&state.mysql_pool)
+    .await
+    .context("failed to fetch round")
+    {
+        Ok(round) => round.and_then(|(datetime, server_port)| {
+            Some(RoundLogs {
+                round_id,
+                server: crate::servers::server_by_port(server_port)?.name,
+                datetime,
+            })
+        }),
+
+        Err(error) => {
+            return super::errors::make_internal_server_error(state, error)
+                .await
+                .into_response();
+        }
+    };
+
+    access_log::record(
+        &state,
+        &user,
+        AccessKind::RoundTickets,
+        &round_id.to_string(),
+    )
+    .await;
+
     render_tickets(
         state,
         params,
@@ -379,6 +435,7 @@ pub async fn for_round(
             who: format!("round {round_id}"),
             page,
             tickets,
+            round,
         },
     )
     .into_response()
@@ -497,6 +554,19 @@ pub async fn for_ticket(
             .into_response();
     }
 
+    if !ticket_messages
+        .iter()
+        .any(|ticket_message| ticket_message.data.sender.as_ref() == Some(&user.ckey))
+    {
+        access_log::record(
+            &state,
+            &user,
+            AccessKind::Ticket,
+            &format!("{round_id}/{ticket}"),
+        )
+        .await;
+    }
+
     state.render_template(
         "ticket",
         TicketTemplate {
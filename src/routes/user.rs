@@ -1,10 +1,39 @@
+use std::sync::Arc;
+
 use axum::{
     extract::Path,
     response::{IntoResponse, Redirect},
+    Extension,
 };
+use serde::Serialize;
+
+use crate::{auth::AuthenticatedUserOptional, State};
+
+use super::TemplateBase;
 
-// Eventually should have a player specific page, but let's do this for now
+#[derive(Serialize)]
+struct UserTemplate {
+    base: TemplateBase,
+}
+
+// Eventually should have a player specific page, but for now only your own is shown
 #[tracing::instrument]
-pub async fn for_ckey(Path(ckey): Path<String>) -> impl IntoResponse {
-    Redirect::temporary(&format!("/tickets/@{ckey}"))
+pub async fn for_ckey(
+    Path(ckey): Path<String>,
+    Extension(state): Extension<Arc<State>>,
+    AuthenticatedUserOptional(user): AuthenticatedUserOptional,
+) -> impl IntoResponse {
+    match user {
+        Some(user) if user.ckey == ckey => state.render_template(
+            "user",
+            UserTemplate {
+                base: TemplateBase {
+                    title: ckey.into(),
+                    user: Some(user),
+                },
+            },
+        ),
+
+        _ => Redirect::temporary(&format!("/tickets/@{ckey}")).into_response(),
+    }
 }
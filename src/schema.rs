@@ -0,0 +1,115 @@
+use color_eyre::eyre::Context;
+
+// Tables owned by mothbus, as opposed to the game's own tables.
+// These are created on startup if they don't exist, and should be prefixed with `mothbus_`.
+const MOTHBUS_TABLES: &[&str] = &[
+    r#"
+    CREATE TABLE IF NOT EXISTS mothbus_pull_requests (
+        repository VARCHAR(255) NOT NULL,
+        number INT UNSIGNED NOT NULL,
+        title TEXT NOT NULL,
+        author VARCHAR(255) NOT NULL,
+        labels TEXT NOT NULL,
+        state VARCHAR(16) NOT NULL,
+        updated_at DATETIME NOT NULL,
+        PRIMARY KEY (repository, number)
+    )
+"#,
+    r#"
+    CREATE TABLE IF NOT EXISTS mothbus_discord_deliveries (
+        id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+        discord_url VARCHAR(512) NOT NULL,
+        payload MEDIUMTEXT NOT NULL,
+        status VARCHAR(16) NOT NULL,
+        attempts INT UNSIGNED NOT NULL DEFAULT 0,
+        last_error TEXT NULL,
+        created_at DATETIME NOT NULL,
+        next_attempt_at DATETIME NOT NULL,
+        PRIMARY KEY (id),
+        INDEX (status, next_attempt_at)
+    )
+"#,
+    r#"
+    CREATE TABLE IF NOT EXISTS mothbus_github_deliveries (
+        id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+        delivery_id VARCHAR(64) NOT NULL,
+        event VARCHAR(64) NOT NULL,
+        action VARCHAR(64) NULL,
+        repository VARCHAR(255) NULL,
+        signature_valid BOOL NOT NULL,
+        outcome TEXT NOT NULL,
+        payload MEDIUMTEXT NOT NULL,
+        payload_truncated BOOL NOT NULL,
+        received_at DATETIME NOT NULL,
+        replay_of BIGINT UNSIGNED NULL,
+        PRIMARY KEY (id),
+        INDEX (received_at)
+    )
+"#,
+    r#"
+    CREATE TABLE IF NOT EXISTS mothbus_sessions (
+        id VARCHAR(64) NOT NULL,
+        ckey VARCHAR(32) NOT NULL,
+        created_at DATETIME NOT NULL,
+        last_seen_at DATETIME NOT NULL,
+        ip VARCHAR(64) NOT NULL,
+        user_agent TEXT NULL,
+        revoked_at DATETIME NULL,
+        PRIMARY KEY (id),
+        INDEX (ckey)
+    )
+"#,
+    r#"
+    CREATE TABLE IF NOT EXISTS mothbus_api_tokens (
+        id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+        label VARCHAR(255) NOT NULL,
+        token_hash CHAR(64) NOT NULL,
+        scopes TEXT NOT NULL,
+        created_by VARCHAR(32) NOT NULL,
+        created_at DATETIME NOT NULL,
+        expires_at DATETIME NULL,
+        revoked_at DATETIME NULL,
+        last_used_at DATETIME NULL,
+        PRIMARY KEY (id),
+        UNIQUE INDEX (token_hash)
+    )
+"#,
+    r#"
+    CREATE TABLE IF NOT EXISTS mothbus_api_token_requests (
+        id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+        token_id BIGINT UNSIGNED NOT NULL,
+        method VARCHAR(16) NOT NULL,
+        path TEXT NOT NULL,
+        status SMALLINT UNSIGNED NOT NULL,
+        ip VARCHAR(64) NOT NULL,
+        requested_at DATETIME NOT NULL,
+        PRIMARY KEY (id),
+        INDEX (token_id),
+        INDEX (requested_at)
+    )
+"#,
+    r#"
+    CREATE TABLE IF NOT EXISTS mothbus_access_log (
+        id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+        viewer VARCHAR(64) NOT NULL,
+        kind VARCHAR(32) NOT NULL,
+        target VARCHAR(255) NOT NULL,
+        viewed_at DATETIME NOT NULL,
+        PRIMARY KEY (id),
+        INDEX (viewer),
+        INDEX (target)
+    )
+"#,
+];
+
+#[tracing::instrument(skip(mysql_pool))]
+pub async fn create_mothbus_tables(mysql_pool: &sqlx::MySqlPool) -> color_eyre::Result<()> {
+    for table in MOTHBUS_TABLES {
+        sqlx::query(table)
+            .execute(mysql_pool)
+            .await
+            .with_context(|| format!("failed to create mothbus table:\n{table}"))?;
+    }
+
+    Ok(())
+}
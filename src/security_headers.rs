@@ -0,0 +1,330 @@
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use axum::response::Response;
+use http::{
+    header::{
+        CONTENT_SECURITY_POLICY, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY,
+        X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+    },
+    HeaderMap, HeaderValue, Request,
+};
+use serde::Deserialize;
+use tower::{Layer, Service};
+
+use crate::{client_info::ClientInfo, State};
+
+/// Everything parcel builds is served from `/static`, with no inline scripts or styles.
+/// `frame-ancestors` is left out, as it depends on whether the page can be embedded.
+const DEFAULT_CONTENT_SECURITY_POLICY: &str = "default-src 'self'; script-src 'self'; \
+    style-src 'self'; img-src 'self' data:; object-src 'none'; base-uri 'none'; form-action 'self'";
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SecurityHeaders {
+    pub content_security_policy: String,
+    pub referrer_policy: String,
+
+    /// Only sent to clients that connected over https. 0 tells browsers to forget it.
+    pub hsts_max_age_seconds: u64,
+
+    /// Other sites that can frame `?embed` fragments, such as `https://tgstation13.org`.
+    /// Full pages can never be framed.
+    pub embed_frame_ancestors: Vec<String>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self {
+            content_security_policy: DEFAULT_CONTENT_SECURITY_POLICY.to_owned(),
+            referrer_policy: "same-origin".to_owned(),
+            hsts_max_age_seconds: 60 * 60 * 24 * 365,
+            embed_frame_ancestors: Vec::new(),
+        }
+    }
+}
+
+impl SecurityHeaders {
+    fn apply(&self, headers: &mut HeaderMap, embed: bool, https: bool) {
+        let frame_ancestors = if embed {
+            std::iter::once("'self'")
+                .chain(self.embed_frame_ancestors.iter().map(String::as_str))
+                .collect::<Vec<_>>()
+                .join(" ")
+        } else {
+            "'none'".to_owned()
+        };
+
+        insert(
+            headers,
+            CONTENT_SECURITY_POLICY,
+            &format!(
+                "{}; frame-ancestors {frame_ancestors}",
+                self.content_security_policy.trim().trim_end_matches(';'),
+            ),
+        );
+
+        // Older browsers only understand this, which can't list other sites
+        if !embed {
+            headers.insert(X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+        } else if self.embed_frame_ancestors.is_empty() {
+            headers.insert(X_FRAME_OPTIONS, HeaderValue::from_static("SAMEORIGIN"));
+        }
+
+        insert(headers, REFERRER_POLICY, &self.referrer_policy);
+        headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+
+        if https {
+            insert(
+                headers,
+                STRICT_TRANSPORT_SECURITY,
+                &format!("max-age={}", self.hsts_max_age_seconds),
+            );
+        }
+    }
+}
+
+fn insert(headers: &mut HeaderMap, name: http::header::HeaderName, value: &str) {
+    match HeaderValue::from_str(value) {
+        Ok(value) => {
+            headers.insert(name, value);
+        }
+
+        Err(error) => tracing::error!("invalid {name} header from config: {error}"),
+    }
+}
+
+/// Adds the headers from `[security_headers]` to every response under it.
+/// Responses that already have a Content-Security-Policy, from a layer further in, are left alone.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SecurityHeadersLayer {
+    allow_embeds: bool,
+}
+
+impl SecurityHeadersLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// For routes that render fragments with `?embed`, which can be framed by `embed_frame_ancestors`.
+    pub fn allowing_embeds() -> Self {
+        Self { allow_embeds: true }
+    }
+}
+
+impl<S> Layer<S> for SecurityHeadersLayer {
+    type Service = SecurityHeadersService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SecurityHeadersService {
+            inner,
+            allow_embeds: self.allow_embeds,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SecurityHeadersService<S> {
+    inner: S,
+    allow_embeds: bool,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for SecurityHeadersService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send,
+    ReqBody: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let state = Arc::clone(
+            request
+                .extensions()
+                .get::<Arc<State>>()
+                .expect("can't get state"),
+        );
+
+        let https = ClientInfo::from_request_ref(&request, &state.config).https;
+
+        let embed = self.allow_embeds
+            && request.uri().query().is_some_and(|query| {
+                form_urlencoded::parse(query.as_bytes()).any(|(key, _)| key == "embed")
+            });
+
+        let future = self.inner.call(request);
+
+        Box::pin(async move {
+            let mut response = future.await?;
+
+            if !response.headers().contains_key(CONTENT_SECURITY_POLICY) {
+                state
+                    .config
+                    .security_headers
+                    .apply(response.headers_mut(), embed, https);
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{body::Body, extract::ConnectInfo};
+    use http::{Method, StatusCode};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn config(extra: &str) -> crate::Config {
+        let mut config = State::test_config(extra);
+        config.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+        config
+    }
+
+    async fn send(
+        app: &axum::Router,
+        method: Method,
+        uri: &str,
+        forwarded_proto: Option<&str>,
+    ) -> Response {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(forwarded_proto) = forwarded_proto {
+            request = request
+                .header("x-forwarded-for", "203.0.113.7")
+                .header("x-forwarded-proto", forwarded_proto);
+        }
+
+        let mut request = request.body(Body::empty()).unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 1234))));
+
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn every_route_has_security_headers() {
+        crate::signing_keys::load_for_tests();
+        let app = crate::router(Arc::new(State::for_tests(config(""))));
+
+        for (method, uri) in [
+            (Method::GET, "/"),
+            (Method::GET, "/@moth"),
+            (Method::GET, "/admin/access-log"),
+            (Method::GET, "/admin/api-tokens"),
+            (Method::GET, "/admin/api-tokens/1"),
+            (Method::GET, "/admin/discord-deliveries"),
+            (Method::GET, "/admin/github-deliveries"),
+            (Method::GET, "/admin/github-deliveries/1"),
+            (Method::GET, "/admin/sessions"),
+            (Method::GET, "/evasion"),
+            (Method::GET, "/login"),
+            (Method::GET, "/mock-login/moth"),
+            (Method::GET, "/oauth"),
+            (Method::GET, "/polls"),
+            (Method::GET, "/polls/1"),
+            (Method::GET, "/polls.json"),
+            (Method::GET, "/polls/1.json"),
+            (Method::GET, "/rank-logs"),
+            (Method::GET, "/recent-test-merges.json"),
+            (Method::GET, "/round-info.json"),
+            (Method::GET, "/sessions"),
+            (Method::GET, "/static/index.js"),
+            (Method::GET, "/tickets"),
+            (Method::GET, "/tickets/1"),
+            (Method::GET, "/tickets/1/1"),
+            (Method::GET, "/tickets/@moth"),
+            (Method::GET, "/tickets/server/sybil"),
+            (Method::GET, "/not-a-page"),
+            (Method::POST, "/admin/api-tokens"),
+            (Method::POST, "/admin/sessions/revoke"),
+            (Method::POST, "/github-webhook"),
+            (Method::POST, "/hooks/round-end"),
+            (Method::POST, "/logout"),
+            (Method::POST, "/sessions/1/revoke"),
+        ] {
+            let response = send(&app, method.clone(), uri, None).await;
+            let headers = response.headers();
+
+            assert_eq!(
+                headers[CONTENT_SECURITY_POLICY],
+                format!("{DEFAULT_CONTENT_SECURITY_POLICY}; frame-ancestors 'none'"),
+                "{method} {uri}"
+            );
+            assert_eq!(headers[X_FRAME_OPTIONS], "DENY", "{method} {uri}");
+            assert_eq!(headers[REFERRER_POLICY], "same-origin", "{method} {uri}");
+            assert_eq!(headers[X_CONTENT_TYPE_OPTIONS], "nosniff", "{method} {uri}");
+
+            // Only over https
+            assert!(
+                !headers.contains_key(STRICT_TRANSPORT_SECURITY),
+                "{method} {uri}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn embeds_can_be_framed_by_configured_sites() {
+        let app = crate::router(Arc::new(State::for_tests(config(indoc::indoc! {r#"
+            [security_headers]
+            embed_frame_ancestors = ["https://tgstation13.org"]
+        "#}))));
+
+        for uri in [
+            "/rank-logs?embed",
+            "/tickets/1?page=2&embed",
+            "/tickets/@moth?embed",
+            "/tickets/server/sybil?embed",
+        ] {
+            let response = send(&app, Method::GET, uri, None).await;
+            let headers = response.headers();
+
+            assert!(
+                headers[CONTENT_SECURITY_POLICY]
+                    .to_str()
+                    .unwrap()
+                    .ends_with("; frame-ancestors 'self' https://tgstation13.org"),
+                "{uri}"
+            );
+            assert!(!headers.contains_key(X_FRAME_OPTIONS), "{uri}");
+        }
+
+        // Only fragments, and only on the routes that render them
+        for uri in [
+            "/rank-logs",
+            "/tickets/1",
+            "/polls?embed",
+            "/sessions?embed",
+        ] {
+            let response = send(&app, Method::GET, uri, None).await;
+            assert_eq!(response.headers()[X_FRAME_OPTIONS], "DENY", "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn hsts_over_https() {
+        let app = crate::router(Arc::new(State::for_tests(config(""))));
+
+        let response = send(&app, Method::GET, "/not-a-page", Some("https")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers()[STRICT_TRANSPORT_SECURITY],
+            "max-age=31536000"
+        );
+
+        let response = send(&app, Method::GET, "/not-a-page", Some("http")).await;
+        assert!(!response.headers().contains_key(STRICT_TRANSPORT_SECURITY));
+    }
+}
@@ -1,39 +1,36 @@
-use std::io::Read;
-
-use once_cell::sync::OnceCell;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 
+use crate::signing_keys;
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Session {
     pub ckey: String,
+    /// The id in `mothbus_sessions`, which is checked so that sessions can be revoked.
+    pub sid: String,
     exp: usize,
 }
 
-pub fn new_session_token(ckey: &str) -> color_eyre::Result<String> {
-    match jsonwebtoken::encode(
-        &jsonwebtoken::Header::default(),
-        &Session {
-            ckey: ckey.to_owned(),
-            exp: (chrono::Utc::now() + chrono::Duration::days(365)).timestamp() as usize,
-        },
-        &jsonwebtoken::EncodingKey::from_secret(get_secret_token()),
-    ) {
+pub const SESSION_LIFETIME_DAYS: i64 = 365;
+
+pub fn new_session_token(ckey: &str, sid: &str) -> color_eyre::Result<String> {
+    match signing_keys::encode(&Session {
+        ckey: ckey.to_owned(),
+        sid: sid.to_owned(),
+        exp: (chrono::Utc::now() + chrono::Duration::days(SESSION_LIFETIME_DAYS)).timestamp()
+            as usize,
+    }) {
         Ok(token) => Ok(token),
         Err(error) => {
             tracing::error!("error creating session token: {error:#?}");
-            Err(error.into())
+            Err(error)
         }
     }
 }
 
 pub fn session_from_token(token: &str) -> Option<Session> {
-    match jsonwebtoken::decode::<Session>(
-        token,
-        &jsonwebtoken::DecodingKey::from_secret(get_secret_token()),
-        &jsonwebtoken::Validation::default(),
-    ) {
-        Ok(token_data) => Some(token_data.claims),
+    match signing_keys::decode::<Session>(token) {
+        Ok(session) => Some(session),
         Err(error) => {
             tracing::debug!("invalid JWT token\n- token: {token}\n- error: {error:#?}");
             None
@@ -41,33 +38,98 @@ pub fn session_from_token(token: &str) -> Option<Session> {
     }
 }
 
-const SECRET_KEY_FILE: &str = "jwt_secret_key.txt";
+/// Sent through the OAuth provider as `&state`, to be checked when the user comes back.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OAuthState {
+    /// Also stored in a cookie, so the state can only be used by the browser that started the login.
+    pub nonce: String,
+    pub return_to: String,
+    purpose: String,
+    exp: usize,
+}
 
-pub fn get_secret_token() -> &'static [u8] {
-    static SECRET_TOKEN: OnceCell<Vec<u8>> = OnceCell::new();
+const OAUTH_STATE_PURPOSE: &str = "oauth_state";
+pub const OAUTH_STATE_LIFETIME: std::time::Duration = std::time::Duration::from_secs(60 * 10);
 
-    SECRET_TOKEN.get_or_init(|| match std::fs::File::open(SECRET_KEY_FILE) {
-        Ok(mut file) => {
-            let mut buf = Vec::new();
-            file.read_to_end(&mut buf).unwrap();
-            buf
-        }
+/// Returns the signed state, along with the nonce to give to the browser.
+pub fn new_oauth_state_token(return_to: &str) -> color_eyre::Result<(String, String)> {
+    let nonce: String = rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
 
-        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
-            tracing::info!("creating new jwt secret key");
+    let token = signing_keys::encode(&OAuthState {
+        nonce: nonce.clone(),
+        return_to: return_to.to_owned(),
+        purpose: OAUTH_STATE_PURPOSE.to_owned(),
+        exp: (chrono::Utc::now().timestamp() as u64 + OAUTH_STATE_LIFETIME.as_secs()) as usize,
+    })?;
 
-            let secret = rand::thread_rng()
-                .sample_iter(&rand::distributions::Alphanumeric)
-                .take(64)
-                .collect();
+    Ok((token, nonce))
+}
 
-            std::fs::write(SECRET_KEY_FILE, &secret).expect("can't write jwt secret key");
+pub fn oauth_state_from_token(token: &str) -> Option<OAuthState> {
+    match signing_keys::decode::<OAuthState>(token) {
+        Ok(oauth_state) if oauth_state.purpose == OAUTH_STATE_PURPOSE => Some(oauth_state),
 
-            secret
-        }
+        Ok(_) => None,
 
         Err(error) => {
-            panic!("can't open jwt secret key: {error}");
+            tracing::debug!("invalid oauth state\n- token: {token}\n- error: {error:#?}");
+            None
+        }
+    }
+}
+
+/// Only allows paths on this site, so that logging in can't be used to redirect elsewhere.
+/// Browsers drop tabs and newlines and read `\` as `/`, so anything but printable ASCII
+/// could turn into `//evil.com`, and couldn't be put in the Location header anyway.
+pub fn sanitize_return_to(return_to: Option<&str>) -> String {
+    match return_to {
+        Some(return_to)
+            if return_to.starts_with('/')
+                && !return_to.starts_with("//")
+                && return_to
+                    .chars()
+                    .all(|character| character.is_ascii_graphic() && character != '\\')
+                && !return_to.starts_with("/login")
+                && !return_to.starts_with("/oauth") =>
+        {
+            return_to.to_owned()
         }
-    })
+
+        _ => "/".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_return_to_paths() {
+        assert_eq!(
+            sanitize_return_to(Some("/tickets/@mothblocks?page=2")),
+            "/tickets/@mothblocks?page=2"
+        );
+        assert_eq!(sanitize_return_to(None), "/");
+        assert_eq!(sanitize_return_to(Some("https://evil.com")), "/");
+        assert_eq!(sanitize_return_to(Some("//evil.com")), "/");
+        assert_eq!(sanitize_return_to(Some("/\\evil.com")), "/");
+        assert_eq!(sanitize_return_to(Some("/login?return_to=/")), "/");
+
+        for sneaky in [
+            "/\t/evil.com",
+            "/\n/evil.com",
+            "/\r\n/evil.com",
+            "/ /evil.com",
+            "/\\/evil.com",
+            "/tickets\\..\\/evil.com",
+            "/\u{7f}/evil.com",
+            "/tickets/é",
+        ] {
+            assert_eq!(sanitize_return_to(Some(sneaky)), "/", "{sneaky:?}");
+        }
+    }
 }
@@ -0,0 +1,239 @@
+use axum::async_trait;
+use color_eyre::eyre::Context;
+use rand::Rng;
+use serde::Serialize;
+use sqlx::{mysql::MySqlRow, Row};
+
+/// Sessions that haven't been seen in this long are pruned.
+const RETENTION_DAYS: i64 = 365;
+
+/// Where a session was logged in from.
+#[derive(Debug)]
+pub struct SessionClient {
+    pub ip: String,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StoredSession {
+    pub id: String,
+    pub ckey: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_seen_at: chrono::NaiveDateTime,
+    pub ip: String,
+    pub user_agent: Option<String>,
+}
+
+impl StoredSession {
+    fn from_row(row: &MySqlRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            ckey: row.try_get("ckey")?,
+            created_at: row.try_get("created_at")?,
+            last_seen_at: row.try_get("last_seen_at")?,
+            ip: row.try_get("ip")?,
+            user_agent: row.try_get("user_agent")?,
+        })
+    }
+}
+
+/// Where sessions are kept, so they can be revoked before their token expires.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    /// Returns the id of the new session.
+    async fn create(&self, ckey: &str, client: &SessionClient) -> color_eyre::Result<String>;
+
+    /// Returns false if the session has been revoked, or doesn't belong to the ckey.
+    /// Otherwise, marks it as just seen.
+    async fn touch(&self, id: &str, ckey: &str) -> color_eyre::Result<bool>;
+
+    /// The ckey's sessions that haven't been revoked, most recently seen first.
+    async fn sessions_for(&self, ckey: &str) -> color_eyre::Result<Vec<StoredSession>>;
+
+    /// Returns false if the ckey had no such session.
+    async fn revoke(&self, ckey: &str, id: &str) -> color_eyre::Result<bool>;
+
+    /// Returns how many sessions were revoked.
+    async fn revoke_all_for(&self, ckey: &str) -> color_eyre::Result<u64>;
+}
+
+fn new_session_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+pub struct MySqlSessionStore {
+    pub mysql_pool: sqlx::MySqlPool,
+}
+
+#[async_trait]
+impl SessionStore for MySqlSessionStore {
+    #[tracing::instrument(skip(self))]
+    async fn create(&self, ckey: &str, client: &SessionClient) -> color_eyre::Result<String> {
+        let id = new_session_id();
+        let now = chrono::Utc::now().naive_utc();
+
+        sqlx::query(
+            "INSERT INTO mothbus_sessions (id, ckey, created_at, last_seen_at, ip, user_agent)
+            VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&id)
+        .bind(ckey)
+        .bind(now)
+        .bind(now)
+        .bind(&client.ip)
+        .bind(&client.user_agent)
+        .execute(&self.mysql_pool)
+        .await
+        .context("failed to insert session")?;
+
+        sqlx::query("DELETE FROM mothbus_sessions WHERE last_seen_at < ?")
+            .bind(now - chrono::Duration::days(RETENTION_DAYS))
+            .execute(&self.mysql_pool)
+            .await
+            .context("failed to prune sessions")?;
+
+        Ok(id)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn touch(&self, id: &str, ckey: &str) -> color_eyre::Result<bool> {
+        let result = sqlx::query(
+            "UPDATE mothbus_sessions
+            SET last_seen_at = ?
+            WHERE id = ? AND ckey = ? AND revoked_at IS NULL",
+        )
+        .bind(chrono::Utc::now().naive_utc())
+        .bind(id)
+        .bind(ckey)
+        .execute(&self.mysql_pool)
+        .await
+        .context("failed to update session")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn sessions_for(&self, ckey: &str) -> color_eyre::Result<Vec<StoredSession>> {
+        let rows = sqlx::query(
+            "SELECT id, ckey, created_at, last_seen_at, ip, user_agent
+            FROM mothbus_sessions
+            WHERE ckey = ? AND revoked_at IS NULL
+            ORDER BY last_seen_at DESC",
+        )
+        .bind(ckey)
+        .fetch_all(&self.mysql_pool)
+        .await
+        .context("failed to fetch sessions")?;
+
+        rows.iter()
+            .map(|row| StoredSession::from_row(row).map_err(Into::into))
+            .collect()
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn revoke(&self, ckey: &str, id: &str) -> color_eyre::Result<bool> {
+        let result = sqlx::query(
+            "UPDATE mothbus_sessions
+            SET revoked_at = ?
+            WHERE id = ? AND ckey = ? AND revoked_at IS NULL",
+        )
+        .bind(chrono::Utc::now().naive_utc())
+        .bind(id)
+        .bind(ckey)
+        .execute(&self.mysql_pool)
+        .await
+        .context("failed to revoke session")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn revoke_all_for(&self, ckey: &str) -> color_eyre::Result<u64> {
+        let result = sqlx::query(
+            "UPDATE mothbus_sessions
+            SET revoked_at = ?
+            WHERE ckey = ? AND revoked_at IS NULL",
+        )
+        .bind(chrono::Utc::now().naive_utc())
+        .bind(ckey)
+        .execute(&self.mysql_pool)
+        .await
+        .context("failed to revoke sessions")?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// Keeps sessions in memory, for testing routes without a database.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemorySessionStore {
+    sessions: std::sync::Mutex<Vec<StoredSession>>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn create(&self, ckey: &str, client: &SessionClient) -> color_eyre::Result<String> {
+        let id = new_session_id();
+        let now = chrono::Utc::now().naive_utc();
+
+        self.sessions.lock().unwrap().push(StoredSession {
+            id: id.clone(),
+            ckey: ckey.to_owned(),
+            created_at: now,
+            last_seen_at: now,
+            ip: client.ip.clone(),
+            user_agent: client.user_agent.clone(),
+        });
+
+        Ok(id)
+    }
+
+    async fn touch(&self, id: &str, ckey: &str) -> color_eyre::Result<bool> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions
+            .iter_mut()
+            .find(|session| session.id == id && session.ckey == ckey)
+        {
+            Some(session) => {
+                session.last_seen_at = chrono::Utc::now().naive_utc();
+                Ok(true)
+            }
+
+            None => Ok(false),
+        }
+    }
+
+    async fn sessions_for(&self, ckey: &str) -> color_eyre::Result<Vec<StoredSession>> {
+        let mut sessions = self
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|session| session.ckey == ckey)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.last_seen_at));
+        Ok(sessions)
+    }
+
+    async fn revoke(&self, ckey: &str, id: &str) -> color_eyre::Result<bool> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let before = sessions.len();
+        sessions.retain(|session| !(session.id == id && session.ckey == ckey));
+        Ok(sessions.len() < before)
+    }
+
+    async fn revoke_all_for(&self, ckey: &str) -> color_eyre::Result<u64> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let before = sessions.len();
+        sessions.retain(|session| session.ckey != ckey);
+        Ok((before - sessions.len()) as u64)
+    }
+}
@@ -0,0 +1,304 @@
+use std::{sync::RwLock, time::Duration};
+
+use color_eyre::eyre::Context;
+use once_cell::sync::OnceCell;
+use rand::Rng;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+const KEYS_FILE: &str = "jwt_keys.json";
+
+/// The single key used before rotation was supported. Tokens without a `kid` were signed with it.
+const LEGACY_SECRET_KEY_FILE: &str = "jwt_secret_key.txt";
+const LEGACY_KID: &str = "legacy";
+
+/// How often the keys file is reread, to pick up rotations done through the CLI.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct SigningKey {
+    kid: String,
+    secret: String,
+    created_at: chrono::NaiveDateTime,
+    retired_at: Option<chrono::NaiveDateTime>,
+}
+
+impl SigningKey {
+    fn new(now: chrono::NaiveDateTime) -> Self {
+        Self {
+            kid: random_string(16),
+            secret: random_string(64),
+            created_at: now,
+            retired_at: None,
+        }
+    }
+
+    /// Retired keys still verify tokens until everything they signed has expired.
+    fn expired(&self, now: chrono::NaiveDateTime) -> bool {
+        self.retired_at.is_some_and(|retired_at| {
+            now > retired_at + chrono::Duration::days(crate::session::SESSION_LIFETIME_DAYS)
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct KeyRing {
+    keys: Vec<SigningKey>,
+}
+
+impl KeyRing {
+    fn current(&self) -> Option<&SigningKey> {
+        self.keys.iter().rev().find(|key| key.retired_at.is_none())
+    }
+
+    fn find(&self, kid: Option<&str>, now: chrono::NaiveDateTime) -> Option<&SigningKey> {
+        let kid = kid.unwrap_or(LEGACY_KID);
+
+        self.keys
+            .iter()
+            .find(|key| key.kid == kid && !key.expired(now))
+    }
+
+    /// Retires the current key in favor of a new one, and returns the new key id.
+    fn rotate(&mut self, now: chrono::NaiveDateTime) -> String {
+        for key in &mut self.keys {
+            key.retired_at.get_or_insert(now);
+        }
+
+        self.keys.retain(|key| !key.expired(now));
+
+        let key = SigningKey::new(now);
+        let kid = key.kid.clone();
+        self.keys.push(key);
+        kid
+    }
+}
+
+static KEY_RING: OnceCell<RwLock<KeyRing>> = OnceCell::new();
+
+/// Reads the signing keys, creating them if they don't exist. Must be called before signing anything.
+pub fn load() -> color_eyre::Result<()> {
+    replace_key_ring(read_key_ring()?);
+    Ok(())
+}
+
+/// A throwaway key that's never written to disk, for tests that sign tokens.
+#[cfg(test)]
+pub fn load_for_tests() {
+    if KEY_RING.get().is_none() {
+        replace_key_ring(KeyRing {
+            keys: vec![SigningKey::new(chrono::Utc::now().naive_utc())],
+        });
+    }
+}
+
+/// Returns the number of keys that can currently verify tokens.
+pub fn key_count() -> usize {
+    let now = chrono::Utc::now().naive_utc();
+
+    key_ring()
+        .read()
+        .expect("signing keys lock poisoned")
+        .keys
+        .iter()
+        .filter(|key| !key.expired(now))
+        .count()
+}
+
+/// Signs new tokens with a new key. Tokens signed with older keys stay valid until they expire.
+pub fn rotate() -> color_eyre::Result<String> {
+    let mut key_ring = read_key_ring()?;
+    let kid = key_ring.rotate(chrono::Utc::now().naive_utc());
+    write_key_ring(&key_ring)?;
+
+    tracing::info!("rotated jwt signing key, now signing with {kid}");
+
+    replace_key_ring(key_ring);
+    Ok(kid)
+}
+
+/// Rotates whenever the current key is older than `rotate_after_days`, if set,
+/// and otherwise just keeps up with rotations made elsewhere.
+pub async fn run_rotation(rotate_after_days: Option<u32>) {
+    loop {
+        tokio::time::sleep(RELOAD_INTERVAL).await;
+
+        if let Err(error) = load() {
+            tracing::error!("failed to reload jwt signing keys: {error:#}");
+            continue;
+        }
+
+        let Some(rotate_after_days) = rotate_after_days else {
+            continue;
+        };
+
+        let current_created_at = key_ring()
+            .read()
+            .expect("signing keys lock poisoned")
+            .current()
+            .map(|key| key.created_at);
+
+        if current_created_at.is_some_and(|created_at| {
+            chrono::Utc::now().naive_utc()
+                > created_at + chrono::Duration::days(rotate_after_days.into())
+        }) {
+            if let Err(error) = rotate() {
+                tracing::error!("failed to rotate jwt signing key: {error:#}");
+            }
+        }
+    }
+}
+
+pub fn encode<T: Serialize>(claims: &T) -> color_eyre::Result<String> {
+    let key_ring = key_ring().read().expect("signing keys lock poisoned");
+    let key = key_ring
+        .current()
+        .ok_or_else(|| color_eyre::eyre::eyre!("no current jwt signing key"))?;
+
+    let header = jsonwebtoken::Header {
+        kid: Some(key.kid.clone()),
+        ..Default::default()
+    };
+
+    jsonwebtoken::encode(
+        &header,
+        claims,
+        &jsonwebtoken::EncodingKey::from_secret(key.secret.as_bytes()),
+    )
+    .context("failed to sign jwt")
+}
+
+pub fn decode<T: DeserializeOwned>(token: &str) -> jsonwebtoken::errors::Result<T> {
+    let header = jsonwebtoken::decode_header(token)?;
+
+    let key_ring = key_ring().read().expect("signing keys lock poisoned");
+    let key = key_ring
+        .find(header.kid.as_deref(), chrono::Utc::now().naive_utc())
+        .ok_or(jsonwebtoken::errors::ErrorKind::InvalidSignature)?;
+
+    jsonwebtoken::decode::<T>(
+        token,
+        &jsonwebtoken::DecodingKey::from_secret(key.secret.as_bytes()),
+        &jsonwebtoken::Validation::default(),
+    )
+    .map(|token_data| token_data.claims)
+}
+
+fn key_ring() -> &'static RwLock<KeyRing> {
+    KEY_RING.get().expect("jwt signing keys were not loaded")
+}
+
+fn replace_key_ring(key_ring: KeyRing) {
+    match KEY_RING.get() {
+        Some(existing) => *existing.write().expect("signing keys lock poisoned") = key_ring,
+        None => {
+            let _ = KEY_RING.set(RwLock::new(key_ring));
+        }
+    }
+}
+
+fn read_key_ring() -> color_eyre::Result<KeyRing> {
+    let mut key_ring = match std::fs::read_to_string(KEYS_FILE) {
+        Ok(contents) => serde_json::from_str::<KeyRing>(&contents)
+            .with_context(|| format!("{KEYS_FILE} is not valid"))?,
+
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => KeyRing::default(),
+
+        Err(error) => return Err(error).with_context(|| format!("can't read {KEYS_FILE}")),
+    };
+
+    let mut changed = false;
+
+    if key_ring.keys.is_empty() {
+        match std::fs::read(LEGACY_SECRET_KEY_FILE) {
+            Ok(secret) => {
+                tracing::info!("importing {LEGACY_SECRET_KEY_FILE} as a jwt signing key");
+
+                key_ring.keys.push(SigningKey {
+                    kid: LEGACY_KID.to_owned(),
+                    secret: String::from_utf8(secret)
+                        .with_context(|| format!("{LEGACY_SECRET_KEY_FILE} is not utf-8"))?,
+                    created_at: chrono::Utc::now().naive_utc(),
+                    retired_at: None,
+                });
+
+                changed = true;
+            }
+
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+
+            Err(error) => {
+                return Err(error).with_context(|| format!("can't read {LEGACY_SECRET_KEY_FILE}"))
+            }
+        }
+    }
+
+    if key_ring.current().is_none() {
+        tracing::info!("creating new jwt signing key");
+        key_ring
+            .keys
+            .push(SigningKey::new(chrono::Utc::now().naive_utc()));
+        changed = true;
+    }
+
+    if changed {
+        write_key_ring(&key_ring)?;
+    }
+
+    Ok(key_ring)
+}
+
+fn write_key_ring(key_ring: &KeyRing) -> color_eyre::Result<()> {
+    // Written to the side first, so that a crash can't leave a half written file
+    let temporary_file = format!("{KEYS_FILE}.tmp");
+
+    std::fs::write(&temporary_file, serde_json::to_string_pretty(key_ring)?)
+        .with_context(|| format!("can't write {temporary_file}"))?;
+
+    std::fs::rename(&temporary_file, KEYS_FILE)
+        .with_context(|| format!("can't replace {KEYS_FILE}"))?;
+
+    Ok(())
+}
+
+fn random_string(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotate_keeps_old_keys_until_expired() {
+        let start = chrono::NaiveDate::from_ymd(2023, 1, 1).and_hms(0, 0, 0);
+
+        let mut key_ring = KeyRing {
+            keys: vec![SigningKey {
+                kid: LEGACY_KID.to_owned(),
+                secret: "hunter2".to_owned(),
+                created_at: start,
+                retired_at: None,
+            }],
+        };
+
+        let new_kid = key_ring.rotate(start);
+        assert_eq!(key_ring.current().unwrap().kid, new_kid);
+
+        // Tokens from before rotation, which have no kid, still verify
+        assert_eq!(key_ring.find(None, start).unwrap().kid, LEGACY_KID);
+        assert!(key_ring.find(Some(&new_kid), start).is_some());
+        assert!(key_ring.find(Some("unknown"), start).is_none());
+
+        let after_expiry =
+            start + chrono::Duration::days(crate::session::SESSION_LIFETIME_DAYS + 1);
+        assert!(key_ring.find(None, after_expiry).is_none());
+
+        key_ring.rotate(after_expiry);
+        assert_eq!(key_ring.keys.len(), 2);
+        assert!(key_ring.keys.iter().all(|key| key.kid != LEGACY_KID));
+    }
+}
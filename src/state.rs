@@ -1,4 +1,4 @@
-use std::{fmt::Debug, sync::Arc, time::Duration};
+use std::{collections::HashMap, fmt::Debug, sync::Arc, time::Duration};
 
 use axum::response::{Html, IntoResponse, Response};
 use color_eyre::eyre::Context;
@@ -9,10 +9,18 @@ use serde::{Deserialize, Serialize};
 use sqlx::{mysql::MySqlPoolOptions, Row};
 
 use crate::{
+    api_tokens::{ApiToken, Scope},
+    github_deliveries::DeliveryDeduplicator,
     handlebars::create_handlebars,
     hide_debug::HideDebug,
+    login_providers::{self, LoginProvider},
+    rate_limit::RateLimiter,
+    relay::{self, Hook},
+    rights::{Permissions, Right, Rights},
     routes::polls::PollCache,
+    schema,
     session::{self, Session},
+    session_store::{MySqlSessionStore, SessionClient, SessionStore},
     Config,
 };
 
@@ -24,43 +32,164 @@ pub struct State {
 
     session_cache: Cache<String, Session>,
     user_cache: Cache<String, User>,
+    used_oauth_states: Cache<String, ()>,
 
     pub poll_cache: HideDebug<PollCache>,
+    pub rate_limiter: RateLimiter,
+
+    pub discord_queue_notify: tokio::sync::Notify,
+    pub github_deliveries: DeliveryDeduplicator,
+    pub hooks: HideDebug<HashMap<String, Hook>>,
+    pub login_providers: HideDebug<Vec<Box<dyn LoginProvider>>>,
+    pub sessions: HideDebug<Box<dyn SessionStore>>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct User {
     pub ckey: String,
     rank: Option<AdminRank>,
+    capabilities: Capabilities,
+
+    /// For forms to send back. Only set for players with a session, once they've been loaded for a request.
+    #[serde(default)]
+    pub csrf_token: Option<String>,
+}
+
+/// What the user's rights allow them to do, worked out from `Permissions` when the user is loaded.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct Capabilities {
+    read_tickets: bool,
+    read_admin_only_polls: bool,
+    read_text_ckeys: bool,
+    manage_webhooks: bool,
+    manage_sessions: bool,
+    manage_api_tokens: bool,
+    read_access_log: bool,
 }
 
 impl User {
-    pub fn admin(&self) -> bool {
-        self.rank.as_ref().map(AdminRank::admin).unwrap_or_default()
+    fn new(ckey: String, rank: Option<AdminRank>, permissions: &Permissions) -> Self {
+        let rights = rank.as_ref().map(|rank| rank.rights).unwrap_or_default();
+
+        // Players without a rank can't do anything, even if a permission requires no rights
+        let allows = |required: &[Right]| rank.is_some() && rights.contains_all(required);
+
+        Self {
+            capabilities: Capabilities {
+                read_tickets: allows(&permissions.read_tickets),
+                read_admin_only_polls: allows(&permissions.read_admin_only_polls),
+                read_text_ckeys: allows(&permissions.read_text_ckeys),
+                manage_webhooks: allows(&permissions.manage_webhooks),
+                manage_sessions: allows(&permissions.manage_sessions),
+                manage_api_tokens: allows(&permissions.manage_api_tokens),
+                read_access_log: allows(&permissions.read_access_log),
+            },
+
+            ckey,
+            rank,
+            csrf_token: None,
+        }
+    }
+
+    /// Tokens act as a user without a rank, who can do whatever the token's scopes allow.
+    /// Scopes their creator could no longer grant, such as after losing their rank, are ignored.
+    pub fn for_api_token(token: &ApiToken, creator: &User) -> Self {
+        let has = |scope| token.scopes.contains(&scope) && creator.can_grant(scope);
+
+        Self {
+            // Colons can't be in ckeys, so this can't be mistaken for a player
+            ckey: format!("token:{}", token.label),
+            rank: None,
+            capabilities: Capabilities {
+                read_tickets: has(Scope::TicketsRead),
+                read_admin_only_polls: has(Scope::PollsRead),
+                read_text_ckeys: has(Scope::PollsReadCkeys),
+                manage_webhooks: false,
+                manage_sessions: false,
+                manage_api_tokens: false,
+                read_access_log: false,
+            },
+            csrf_token: None,
+        }
+    }
+
+    /// Whether the user can create tokens with this scope, which they can only do if they could use it themselves.
+    pub fn can_grant(&self, scope: Scope) -> bool {
+        self.can_manage_api_tokens()
+            && match scope {
+                Scope::TicketsRead => self.can_read_tickets(),
+                Scope::PollsRead => self.can_read_admin_only_polls(),
+                Scope::PollsReadCkeys => self.can_read_text_ckeys(),
+            }
     }
 
     pub fn can_read_tickets(&self) -> bool {
-        self.admin()
+        self.capabilities.read_tickets
     }
 
     pub fn can_read_admin_only_polls(&self) -> bool {
-        self.admin()
+        self.capabilities.read_admin_only_polls
     }
 
     pub fn can_read_text_ckeys(&self) -> bool {
-        self.admin()
+        self.capabilities.read_text_ckeys
+    }
+
+    pub fn can_manage_webhooks(&self) -> bool {
+        self.capabilities.manage_webhooks
+    }
+
+    pub fn can_manage_sessions(&self) -> bool {
+        self.capabilities.manage_sessions
+    }
+
+    pub fn can_manage_api_tokens(&self) -> bool {
+        self.capabilities.manage_api_tokens
+    }
+
+    pub fn can_read_access_log(&self) -> bool {
+        self.capabilities.read_access_log
     }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct AdminRank {
     name: String,
+    rights: Rights,
+    /// The rights this rank can give to others in game.
+    can_edit_rights: Rights,
+}
+
+/// A row from `admin_ranks`.
+#[derive(Clone, Copy, Debug)]
+struct RankFlags {
     flags: u32,
+    exclude_flags: u32,
+    can_edit_flags: u32,
 }
 
 impl AdminRank {
-    pub fn admin(&self) -> bool {
-        self.flags & (1 << 1) != 0
+    /// Ranks such as `Game Admin+Banned` combine several `admin_ranks`. As in game, everything is
+    /// unioned before excludes are taken away, so the order the ranks are listed in doesn't matter.
+    fn combine(name: String, ranks: &[RankFlags]) -> Self {
+        let union = ranks.iter().fold(
+            RankFlags {
+                flags: 0,
+                exclude_flags: 0,
+                can_edit_flags: 0,
+            },
+            |union, rank| RankFlags {
+                flags: union.flags | rank.flags,
+                exclude_flags: union.exclude_flags | rank.exclude_flags,
+                can_edit_flags: union.can_edit_flags | rank.can_edit_flags,
+            },
+        );
+
+        Self {
+            name,
+            rights: Rights::from_bits(union.flags & !union.exclude_flags),
+            can_edit_rights: Rights::from_bits(union.can_edit_flags),
+        }
     }
 }
 
@@ -87,17 +216,110 @@ impl State {
     pub async fn new() -> color_eyre::Result<Self> {
         let config = Config::read_from_file().context("failed to read config")?;
 
-        Ok(Self {
-            handlebars: HideDebug(create_handlebars()?),
-            mysql_pool: create_mysql_pool(&config).await?,
+        let mysql_pool = create_mysql_pool(&config).await?;
+        schema::create_mothbus_tables(&mysql_pool).await?;
+
+        Ok(Self::from_parts(
+            create_handlebars(&config)?,
+            DeliveryDeduplicator::load(&mysql_pool).await?,
+            Box::new(MySqlSessionStore {
+                mysql_pool: mysql_pool.clone(),
+            }),
+            mysql_pool,
+            config,
+        ))
+    }
+
+    /// With only a plain error template, sessions kept in memory, and a database that's only
+    /// connected to when used, for testing routes that don't need it.
+    #[cfg(test)]
+    pub fn for_tests(config: Config) -> Self {
+        let mut handlebars = Handlebars::new();
+        handlebars
+            .register_template_string("error", "{{ error_code }}: {{ error_message }}")
+            .expect("invalid test error template");
+
+        Self::from_parts(
+            handlebars,
+            DeliveryDeduplicator::empty(),
+            Box::<crate::session_store::MemorySessionStore>::default(),
+            // There's no database in tests, so routes that need it should fail quickly
+            MySqlPoolOptions::new()
+                .acquire_timeout(Duration::from_secs(1))
+                .connect_lazy("mysql://localhost/mothbus_tests")
+                .expect("invalid test database url"),
+            config,
+        )
+    }
+
+    /// The least config that parses, with `extra` appended for tests that need more, such as
+    /// a `[rate_limits]` table.
+    #[cfg(test)]
+    pub fn test_config(extra: &str) -> Config {
+        toml::from_str(&format!(
+            "{}{extra}",
+            indoc::indoc! {r#"
+                address = "127.0.0.1"
+                port = 2222
+                db_url = "mysql://localhost/mothbus_tests"
+
+                [github_webhook]
+                secret = "hunter2"
+
+                [oauth2]
+                client_id = ""
+                client_secret = ""
+                redirect_uri = ""
+            "#}
+        ))
+        .expect("invalid test config")
+    }
+
+    fn from_parts(
+        handlebars: Handlebars<'static>,
+        github_deliveries: DeliveryDeduplicator,
+        sessions: Box<dyn SessionStore>,
+        mysql_pool: sqlx::MySqlPool,
+        config: Config,
+    ) -> Self {
+        Self {
+            handlebars: HideDebug(handlebars),
 
             session_cache: small_cache(),
             user_cache: small_cache(),
+            used_oauth_states: Cache::builder()
+                .max_capacity(10_000)
+                .time_to_live(session::OAUTH_STATE_LIFETIME)
+                .build(),
 
             poll_cache: HideDebug(PollCache::new()),
+            rate_limiter: RateLimiter::new(&config.rate_limits),
+
+            discord_queue_notify: tokio::sync::Notify::new(),
+            github_deliveries,
+            hooks: HideDebug(relay::hooks_from_config(&config.hooks)),
+            login_providers: HideDebug(login_providers::login_providers_from_config(&config)),
+            sessions: HideDebug(sessions),
+            mysql_pool,
 
             config: HideDebug(config),
-        })
+        }
+    }
+
+    pub fn login_provider(&self, name: &str) -> Option<&dyn LoginProvider> {
+        self.login_providers
+            .iter()
+            .find(|provider| provider.name() == name)
+            .map(AsRef::as_ref)
+    }
+
+    /// Returns false if the OAuth state with this nonce has already been used.
+    pub async fn claim_oauth_state(&self, nonce: &str) -> bool {
+        self.used_oauth_states
+            .entry_by_ref(nonce)
+            .or_insert(())
+            .await
+            .is_fresh()
     }
 
     pub fn render_template<T: Serialize>(&self, path: &'static str, data: T) -> Response {
@@ -108,9 +330,7 @@ impl State {
 
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
-                    format!(
-                        "failed to render template. this is a bug. please report to mothblocks"
-                    ),
+                    "failed to render template. this is a bug. please report to mothblocks",
                 )
                     .into_response()
             }
@@ -142,6 +362,13 @@ impl State {
             None => return Ok(None),
         };
 
+        // Only checked when the session isn't cached. Revoking clears the cache,
+        // so it takes effect straight away.
+        if !self.sessions.touch(&session.sid, &session.ckey).await? {
+            tracing::debug!("session {} has been revoked", session.sid);
+            return Ok(None);
+        }
+
         self.session_cache
             .insert(session_jwt.to_string(), session)
             .await;
@@ -160,46 +387,52 @@ impl State {
             return Ok(user);
         }
 
-        let rank = match sqlx::query("SELECT rank FROM admin WHERE ckey = ?")
+        // Temporary ranks given in game are never written to `admin`, and nothing in the admin
+        // tables expires, so only permanent ranks are seen here. Rank changes take effect once
+        // the user cache expires.
+        let rank_name: Option<String> = sqlx::query_scalar("SELECT rank FROM admin WHERE ckey = ?")
             .bind(ckey)
             .fetch_optional(&self.mysql_pool)
             .await
-        {
-            Ok(Some(admin_rank)) => {
-                let rank_name: String = admin_rank.get("rank");
-                let mut flags: u32 = 0;
-
-                for rank in rank_name.split('+') {
-                    match sqlx::query("SELECT flags, exclude_flags FROM admin_ranks WHERE rank = ?")
-                        .bind(rank)
-                        .fetch_optional(&self.mysql_pool)
-                        .await
-                    {
-                        Ok(Some(rank)) => {
-                            flags |= rank.get::<u32, _>("flags");
-                            flags &= !rank.get::<u32, _>("exclude_flags");
-                        }
-
-                        Ok(None) => {}
-
-                        Err(error) => return Err(error.into()),
-                    }
-                }
+            .context("failed to fetch admin rank")?;
+
+        let rank = match rank_name {
+            Some(rank_name) => {
+                let rank_names = rank_name.split('+').map(str::trim).collect::<Vec<_>>();
 
-                Some(AdminRank {
-                    name: rank_name,
-                    flags,
-                })
+                let mut query = sqlx::QueryBuilder::new(
+                    "SELECT flags, exclude_flags, can_edit_flags FROM admin_ranks WHERE rank IN (",
+                );
+
+                let mut separated = query.separated(", ");
+                for rank_name in &rank_names {
+                    separated.push_bind(*rank_name);
+                }
+                separated.push_unseparated(")");
+
+                let rank_flags = query
+                    .build()
+                    .fetch_all(&self.mysql_pool)
+                    .await
+                    .context("failed to fetch admin rank flags")?
+                    .iter()
+                    .map(|row| {
+                        Ok(RankFlags {
+                            flags: row.try_get("flags")?,
+                            exclude_flags: row.try_get("exclude_flags")?,
+                            can_edit_flags: row.try_get("can_edit_flags")?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, sqlx::Error>>()?;
+
+                Some(AdminRank::combine(rank_name, &rank_flags))
             }
-            Ok(None) => None,
-            Err(error) => return Err(error.into()),
-        };
 
-        let user = User {
-            ckey: ckey.to_string(),
-            rank,
+            None => None,
         };
 
+        let user = User::new(ckey.to_string(), rank, &self.config.permissions);
+
         self.user_cache.insert(ckey.to_string(), user).await;
 
         Ok(self.user_cache.get(&ckey.to_string()).await.unwrap())
@@ -207,7 +440,201 @@ impl State {
 
     /// Creates the session, and returns the session key
     #[tracing::instrument]
-    pub async fn create_session_for(self: Arc<Self>, ckey: &str) -> color_eyre::Result<String> {
-        session::new_session_token(ckey)
+    pub async fn create_session_for(
+        self: Arc<Self>,
+        ckey: &str,
+        client: &SessionClient,
+    ) -> color_eyre::Result<String> {
+        let sid = self.sessions.create(ckey, client).await?;
+        session::new_session_token(ckey, &sid)
+    }
+
+    /// Logs the ckey in with a rank holding these rights, or as a player without one,
+    /// without going through the admin tables. Returns the session key.
+    #[cfg(test)]
+    pub async fn log_in_for_tests(self: Arc<Self>, ckey: &str, rights: Option<&[Right]>) -> String {
+        let rank = rights.map(|rights| AdminRank {
+            name: "Test Rank".to_owned(),
+            rights: Rights::from(rights.to_vec()),
+            can_edit_rights: Rights::default(),
+        });
+
+        self.user_cache
+            .insert(
+                ckey.to_owned(),
+                User::new(ckey.to_owned(), rank, &self.config.permissions),
+            )
+            .await;
+
+        self.create_session_for(
+            ckey,
+            &SessionClient {
+                ip: "127.0.0.1".to_owned(),
+                user_agent: None,
+            },
+        )
+        .await
+        .expect("couldn't create test session")
+    }
+
+    /// Returns false if the ckey had no such session.
+    #[tracing::instrument]
+    pub async fn revoke_session(&self, ckey: &str, sid: &str) -> color_eyre::Result<bool> {
+        let revoked = self.sessions.revoke(ckey, sid).await?;
+        self.session_cache.invalidate_all();
+        Ok(revoked)
+    }
+
+    /// Returns how many sessions were revoked.
+    #[tracing::instrument]
+    pub async fn revoke_all_sessions_for(&self, ckey: &str) -> color_eyre::Result<u64> {
+        let revoked = self.sessions.revoke_all_for(ckey).await?;
+        self.session_cache.invalidate_all();
+        Ok(revoked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADMIN: u32 = 1 << 1;
+    const BAN: u32 = 1 << 2;
+    const FUN: u32 = 1 << 3;
+
+    fn rank(flags: u32, exclude_flags: u32, can_edit_flags: u32) -> RankFlags {
+        RankFlags {
+            flags,
+            exclude_flags,
+            can_edit_flags,
+        }
+    }
+
+    fn rights(rank: &AdminRank) -> Vec<Right> {
+        rank.rights.iter().collect()
+    }
+
+    #[test]
+    fn combine_single_rank() {
+        let combined = AdminRank::combine("Game Admin".to_owned(), &[rank(ADMIN | BAN, 0, FUN)]);
+
+        assert_eq!(rights(&combined), [Right::Admin, Right::Ban]);
+        assert_eq!(
+            combined.can_edit_rights.iter().collect::<Vec<_>>(),
+            [Right::Fun]
+        );
+    }
+
+    #[test]
+    fn combine_excludes_after_union() {
+        let game_admin = rank(ADMIN | BAN, 0, 0);
+        let no_bans = rank(0, BAN, 0);
+        let fun = rank(FUN | BAN, 0, BAN);
+
+        // An exclude wins even if a later rank grants the right again
+        for ranks in [
+            [game_admin, no_bans, fun],
+            [fun, no_bans, game_admin],
+            [no_bans, fun, game_admin],
+        ] {
+            let combined = AdminRank::combine("Game Admin+No Bans+Fun".to_owned(), &ranks);
+            assert_eq!(rights(&combined), [Right::Admin, Right::Fun]);
+            assert_eq!(
+                combined.can_edit_rights.iter().collect::<Vec<_>>(),
+                [Right::Ban]
+            );
+        }
+    }
+
+    #[test]
+    fn combine_missing_ranks() {
+        let combined = AdminRank::combine("Deleted Rank".to_owned(), &[]);
+        assert!(rights(&combined).is_empty());
+
+        let user = User::new(
+            "mothblocks".to_owned(),
+            Some(combined),
+            &Permissions::default(),
+        );
+        assert!(!user.can_read_tickets());
+    }
+
+    #[test]
+    fn text_ckeys_need_their_own_scope() {
+        let permissions = Permissions {
+            read_text_ckeys: vec![Right::Ban],
+            ..Permissions::default()
+        };
+
+        let admin = User::new(
+            "mothblocks".to_owned(),
+            Some(AdminRank::combine(
+                "Trial Admin".to_owned(),
+                &[rank(ADMIN, 0, 0)],
+            )),
+            &permissions,
+        );
+
+        assert!(admin.can_grant(Scope::PollsRead));
+        assert!(!admin.can_grant(Scope::PollsReadCkeys));
+
+        let head_admin = User::new(
+            "mothblocks".to_owned(),
+            Some(AdminRank::combine(
+                "Headmin".to_owned(),
+                &[rank(ADMIN | BAN, 0, 0)],
+            )),
+            &permissions,
+        );
+
+        let token = |scopes: Vec<Scope>| {
+            User::for_api_token(
+                &ApiToken {
+                    id: 1,
+                    label: "discord bot".to_owned(),
+                    scopes,
+                    created_by: "mothblocks".to_owned(),
+                },
+                &head_admin,
+            )
+        };
+
+        let polls = token(vec![Scope::PollsRead]);
+        assert!(polls.can_read_admin_only_polls());
+        assert!(!polls.can_read_text_ckeys());
+
+        let ckeys = token(vec![Scope::PollsReadCkeys]);
+        assert!(ckeys.can_read_text_ckeys());
+        assert!(!ckeys.can_read_admin_only_polls());
+    }
+
+    #[test]
+    fn tokens_lose_what_their_creator_loses() {
+        let token = ApiToken {
+            id: 1,
+            label: "discord bot".to_owned(),
+            scopes: vec![Scope::TicketsRead, Scope::PollsRead],
+            created_by: "mothblocks".to_owned(),
+        };
+
+        let admin = User::new(
+            "mothblocks".to_owned(),
+            Some(AdminRank::combine(
+                "Game Admin".to_owned(),
+                &[rank(ADMIN, 0, 0)],
+            )),
+            &Permissions::default(),
+        );
+
+        let user = User::for_api_token(&token, &admin);
+        assert!(user.can_read_tickets());
+        assert!(user.can_read_admin_only_polls());
+
+        // De-adminned since making the token
+        let player = User::new("mothblocks".to_owned(), None, &Permissions::default());
+
+        let user = User::for_api_token(&token, &player);
+        assert!(!user.can_read_tickets());
+        assert!(!user.can_read_admin_only_polls());
     }
 }
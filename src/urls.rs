@@ -0,0 +1,103 @@
+use serde::Deserialize;
+
+/// Links to external sites, configurable so that downstream servers can point at their own hosts.
+/// Placeholders are written as `{name}`, and unknown placeholders are left alone.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct UrlTemplates {
+    /// Placeholders: `{server}`, `{round_id}`, `{year}`, `{month}`, `{day}`
+    pub round_logs: String,
+
+    /// Placeholders: `{round_id}`, `{ticket}`
+    pub statbus_ticket: String,
+
+    /// Placeholders: `{pr}`
+    pub github_pull_request: String,
+
+    /// Where this mothbus is hosted, for links sent outside of it. Placeholders: `{round_id}`
+    pub mothbus_round: String,
+}
+
+impl Default for UrlTemplates {
+    fn default() -> Self {
+        Self {
+            round_logs: "https://tgstation13.org/parsed-logs/{server}/data/logs/{year}/{month}/{day}/round-{round_id}/".to_owned(),
+            statbus_ticket: "https://statbus.space/tickets/{round_id}/{ticket}".to_owned(),
+            github_pull_request: "https://github.com/tgstation/tgstation/pull/{pr}".to_owned(),
+            mothbus_round: "https://moth.fans/tickets/{round_id}".to_owned(),
+        }
+    }
+}
+
+impl UrlTemplates {
+    pub fn round_logs(
+        &self,
+        server: &str,
+        round_id: u64,
+        datetime: chrono::NaiveDateTime,
+    ) -> String {
+        fill_template(
+            &self.round_logs,
+            &[
+                ("server", server.to_owned()),
+                ("round_id", round_id.to_string()),
+                ("year", datetime.format("%Y").to_string()),
+                ("month", datetime.format("%m").to_string()),
+                ("day", datetime.format("%d").to_string()),
+            ],
+        )
+    }
+
+    pub fn statbus_ticket(&self, round_id: u64, ticket: u64) -> String {
+        fill_template(
+            &self.statbus_ticket,
+            &[
+                ("round_id", round_id.to_string()),
+                ("ticket", ticket.to_string()),
+            ],
+        )
+    }
+
+    pub fn github_pull_request(&self, pr: u64) -> String {
+        fill_template(&self.github_pull_request, &[("pr", pr.to_string())])
+    }
+
+    pub fn mothbus_round(&self, round_id: u64) -> String {
+        fill_template(&self.mothbus_round, &[("round_id", round_id.to_string())])
+    }
+}
+
+fn fill_template(template: &str, values: &[(&str, String)]) -> String {
+    let mut output = template.to_owned();
+
+    for (name, value) in values {
+        output = output.replace(&format!("{{{name}}}"), value);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_round_logs() {
+        assert_eq!(
+            UrlTemplates::default().round_logs(
+                "manuel",
+                219951,
+                chrono::NaiveDate::from_ymd(2023, 12, 4).and_hms(18, 30, 0),
+            ),
+            "https://tgstation13.org/parsed-logs/manuel/data/logs/2023/12/04/round-219951/"
+        );
+    }
+
+    #[test]
+    fn fill_template_unknown_placeholder() {
+        assert_eq!(
+            fill_template("{pr}/{unknown}", &[("pr", "123".to_owned())]),
+            "123/{unknown}"
+        );
+    }
+}